@@ -1,9 +1,11 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 
 use color_eyre::{eyre::WrapErr, Result};
 
-pub fn clean(app_path: &PathBuf) -> Result<()> {
+use crate::packaging::{prune_artifacts, remove_kept_build_dirs};
+
+pub fn clean(app_path: &Path) -> Result<()> {
     let artifacts_path = app_path.join("build").join("artifacts");
 
     if artifacts_path.exists() {
@@ -14,3 +16,26 @@ pub fn clean(app_path: &PathBuf) -> Result<()> {
     }
     Ok(())
 }
+
+pub fn clean_build_dirs(app_path: &Path) -> Result<()> {
+    let removed =
+        remove_kept_build_dirs(app_path).wrap_err("Failed to remove kept build directories")?;
+
+    if removed > 0 {
+        println!("Removed {} kept build directories.", removed);
+    } else {
+        println!("No kept build directories found.");
+    }
+    Ok(())
+}
+
+pub fn clean_artifacts(app_path: &Path, keep: usize) -> Result<()> {
+    let removed = prune_artifacts(app_path, keep).wrap_err("Failed to remove old artifacts")?;
+
+    if removed > 0 {
+        println!("Removed {} old builds, kept the newest {}.", removed, keep);
+    } else {
+        println!("No old builds found.");
+    }
+    Ok(())
+}
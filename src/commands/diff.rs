@@ -0,0 +1,9 @@
+use std::path::Path;
+
+use color_eyre::Result;
+
+use crate::packaging::diff_packages;
+
+pub fn diff_archives(app_path: &Path, old_path: &Path, new_path: Option<&Path>) -> Result<()> {
+    diff_packages(app_path, old_path, new_path)
+}
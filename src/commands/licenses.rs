@@ -0,0 +1,9 @@
+use std::path::Path;
+
+use color_eyre::Result;
+
+use crate::packaging::list_licenses;
+
+pub fn licenses(app_path: &Path, profile: Option<&str>) -> Result<()> {
+    list_licenses(app_path, profile)
+}
@@ -1,19 +1,27 @@
 mod clean;
+mod diff;
 mod disable;
 mod enable;
 mod init;
+mod licenses;
 mod login;
 mod package;
 mod sign_package;
 mod up;
+mod verify;
+mod verify_signature;
 mod version;
 
-pub use self::clean::clean;
+pub use self::clean::{clean, clean_artifacts, clean_build_dirs};
+pub use self::diff::diff_archives;
 pub use self::disable::disable_app;
 pub use self::enable::enable_app;
 pub use self::init::init;
+pub use self::licenses::licenses;
 pub use self::login::*;
 pub use self::package::package_app;
 pub use self::sign_package::sign_package;
 pub use self::up::up;
+pub use self::verify::verify_archive;
+pub use self::verify_signature::verify_signature;
 pub use self::version::*;
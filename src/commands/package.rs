@@ -1,9 +1,10 @@
-use std::path::PathBuf;
+use std::path::Path;
 
 use color_eyre::Result;
 
 use crate::packaging::package_app as package;
+use crate::packaging::PackageOptions;
 
-pub fn package_app(app_path: &PathBuf, shipped: bool) -> Result<()> {
-    package(app_path, shipped)
+pub fn package_app(app_path: &Path, options: PackageOptions) -> Result<()> {
+    package(app_path, options)
 }
@@ -1,10 +1,10 @@
-use std::fs::File;
 use std::path::{Path, PathBuf};
 
 use color_eyre::{eyre::WrapErr, Report, Result};
 use dirs::home_dir;
 use nextcloud_appinfo::get_appinfo;
-use nextcloud_appsignature;
+
+use crate::packaging::sign_archive;
 
 fn get_home_dir() -> Result<PathBuf> {
     home_dir().ok_or(Report::msg("Could not resolve home dir"))
@@ -41,9 +41,7 @@ pub fn sign_package() -> Result<String> {
         ));
     }
 
-    let mut package = File::open(&package_path)?;
-    let signature = nextcloud_appsignature::sign_package(&key_path, &mut package)
-        .wrap_err("Failed to sign package")?;
+    let signature = sign_archive(&package_path, &key_path).wrap_err("Failed to sign package")?;
 
     Ok(signature)
 }
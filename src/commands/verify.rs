@@ -0,0 +1,11 @@
+use std::path::Path;
+
+use color_eyre::Result;
+
+use crate::packaging::verify_archive as verify;
+
+pub fn verify_archive(app_path: &Path, archive_path: Option<&Path>) -> Result<()> {
+    let archive_path = verify(app_path, archive_path)?;
+    println!("Archive {:?} is valid.", archive_path);
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use std::path::Path;
+
+use color_eyre::Result;
+
+use crate::packaging::verify_signature as verify;
+
+pub fn verify_signature(
+    app_path: &Path,
+    archive_path: Option<&Path>,
+    signature: &str,
+    certificate_path: &Path,
+) -> Result<()> {
+    let archive_path = verify(app_path, archive_path, signature, certificate_path)?;
+    println!("Signature of {:?} is valid.", archive_path);
+    Ok(())
+}
@@ -1,9 +1,12 @@
-use std::convert::Into;
+use std::collections::{BTreeMap, HashMap};
 use std::default::Default;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use color_eyre::{eyre::WrapErr, Report, Result};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
 use toml;
 
 use super::{ConfigFileReader, ConfigReader};
@@ -13,64 +16,638 @@ struct ParsedAppConfig {
     package: Option<ParsedPackageConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct ParsedPackageConfig {
-    before_cmds: Option<Vec<String>>,
+    before_cmds: Option<Vec<ParsedStep>>,
+    after_cmds: Option<Vec<ParsedStep>>,
+    dependencies: Option<ParsedDependencyConfig>,
+    limits: Option<ParsedLimitsConfig>,
+    licenses: Option<ParsedLicensesConfig>,
+    keep_build_dir_on_failure: Option<bool>,
+    ignore_file: Option<String>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_artifact_name")]
+    artifact_name: Option<String>,
+    keep_artifacts: Option<usize>,
+    build_info: Option<bool>,
+    code_signing: Option<ParsedCodeSigningConfig>,
+    verify: Option<bool>,
+    profiles: Option<HashMap<String, ParsedPackageConfig>>,
 }
 
-#[derive(Debug)]
+impl ParsedPackageConfig {
+    /// Overrides all values that are set by the given profile
+    fn merge(self, profile: ParsedPackageConfig) -> ParsedPackageConfig {
+        ParsedPackageConfig {
+            before_cmds: profile.before_cmds.or(self.before_cmds),
+            after_cmds: profile.after_cmds.or(self.after_cmds),
+            dependencies: match (self.dependencies, profile.dependencies) {
+                (Some(base), Some(profile)) => Some(base.merge(profile)),
+                (base, profile) => profile.or(base),
+            },
+            limits: match (self.limits, profile.limits) {
+                (Some(base), Some(profile)) => Some(base.merge(profile)),
+                (base, profile) => profile.or(base),
+            },
+            licenses: match (self.licenses, profile.licenses) {
+                (Some(base), Some(profile)) => Some(base.merge(profile)),
+                (base, profile) => profile.or(base),
+            },
+            keep_build_dir_on_failure: profile
+                .keep_build_dir_on_failure
+                .or(self.keep_build_dir_on_failure),
+            ignore_file: profile.ignore_file.or(self.ignore_file),
+            include: profile.include.or(self.include),
+            exclude: profile.exclude.or(self.exclude),
+            artifact_name: profile.artifact_name.or(self.artifact_name),
+            keep_artifacts: profile.keep_artifacts.or(self.keep_artifacts),
+            build_info: profile.build_info.or(self.build_info),
+            code_signing: match (self.code_signing, profile.code_signing) {
+                (Some(base), Some(profile)) => Some(base.merge(profile)),
+                (base, profile) => profile.or(base),
+            },
+            verify: profile.verify.or(self.verify),
+            profiles: None,
+        }
+    }
+}
+
+/// A step is a single command or a group of commands run in parallel
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ParsedStep {
+    Parallel(ParsedParallelStep),
+    Command(ParsedCommand),
+}
+
+/// Rejects unknown options like the commands it contains
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParsedParallelStep {
+    parallel: Vec<ParsedCommand>,
+}
+
+/// Commands are either plain strings or tables with additional options
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ParsedCommand {
+    Plain(String),
+    Structured(ParsedStructuredCommand),
+}
+
+/// Unknown options are rejected, a misspelled `timeout` must not be ignored
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParsedStructuredCommand {
+    run: String,
+    name: Option<String>,
+    cwd: Option<PathBuf>,
+    env: Option<BTreeMap<String, String>>,
+    timeout: Option<u64>,
+    allow_failure: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ParsedDependencyConfig {
+    install: Option<bool>,
+    composer_cmd: Option<String>,
+    npm_cmd: Option<String>,
+}
+
+impl ParsedDependencyConfig {
+    fn merge(self, profile: ParsedDependencyConfig) -> ParsedDependencyConfig {
+        ParsedDependencyConfig {
+            install: profile.install.or(self.install),
+            composer_cmd: profile.composer_cmd.or(self.composer_cmd),
+            npm_cmd: profile.npm_cmd.or(self.npm_cmd),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ParsedLimitsConfig {
+    #[serde(default, deserialize_with = "deserialize_size")]
+    max_archive_size: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    max_file_size: Option<u64>,
+}
+
+impl ParsedLimitsConfig {
+    fn merge(self, profile: ParsedLimitsConfig) -> ParsedLimitsConfig {
+        ParsedLimitsConfig {
+            max_archive_size: profile.max_archive_size.or(self.max_archive_size),
+            max_file_size: profile.max_file_size.or(self.max_file_size),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ParsedLicensesConfig {
+    allowed: Option<Vec<String>>,
+    check: Option<bool>,
+}
+
+impl ParsedLicensesConfig {
+    fn merge(self, profile: ParsedLicensesConfig) -> ParsedLicensesConfig {
+        ParsedLicensesConfig {
+            allowed: profile.allowed.or(self.allowed),
+            check: profile.check.or(self.check),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ParsedCodeSigningConfig {
+    enabled: Option<bool>,
+    key: Option<PathBuf>,
+    certificate: Option<PathBuf>,
+}
+
+impl ParsedCodeSigningConfig {
+    fn merge(self, profile: ParsedCodeSigningConfig) -> ParsedCodeSigningConfig {
+        ParsedCodeSigningConfig {
+            enabled: profile.enabled.or(self.enabled),
+            key: profile.key.or(self.key),
+            certificate: profile.certificate.or(self.certificate),
+        }
+    }
+}
+
+/// Sizes are given in bytes or as strings like `"1.5 MiB"` or `"800 kB"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParsedSize {
+    Bytes(u64),
+    Text(String),
+}
+
+fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let factor: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "k" | "kib" => 1024,
+        "mb" => 1000 * 1000,
+        "m" | "mib" => 1024 * 1024,
+        "gb" => 1000 * 1000 * 1000,
+        "g" | "gib" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    Some((number * factor as f64) as u64)
+}
+
+fn deserialize_size<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<ParsedSize>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(ParsedSize::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(ParsedSize::Text(text)) => parse_size(&text)
+            .map(Some)
+            .ok_or_else(|| D::Error::custom(format!("invalid size <{}>", text))),
+    }
+}
+
+/// Artifacts are written to `build/artifacts`, their names must not point
+/// anywhere else
+pub fn is_artifact_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/') && !name.contains('\\') && !name.contains("..")
+}
+
+fn deserialize_artifact_name<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(name) if !is_artifact_name(&name) => Err(D::Error::custom(format!(
+            "invalid artifact name <{}>, it must not contain path separators or ..",
+            name
+        ))),
+        name => Ok(name),
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct AppConfig {
     package: PackageConfig,
+    profile: Option<String>,
 }
 
 impl AppConfig {
     pub fn package(&self) -> &PackageConfig {
         &self.package
     }
+
+    pub fn profile(&self) -> Option<&String> {
+        self.profile.as_ref()
+    }
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
+impl From<ParsedAppConfig> for AppConfig {
+    fn from(parsed: ParsedAppConfig) -> Self {
         AppConfig {
-            package: PackageConfig::default(),
+            package: parsed.package.map(|pc| pc.into()).unwrap_or_default(),
+            profile: None,
         }
     }
 }
 
-impl Into<AppConfig> for ParsedAppConfig {
-    fn into(self) -> AppConfig {
-        AppConfig {
-            package: self
-                .package
-                .map(|pc| pc.into())
-                .unwrap_or(PackageConfig::default()),
-        }
+impl ParsedAppConfig {
+    /// Applies the named profile on top of the base package config
+    fn into_profile(self, profile: &str) -> Result<AppConfig> {
+        let mut package = self.package.unwrap_or_default();
+        let profile_config = package
+            .profiles
+            .take()
+            .and_then(|mut profiles| profiles.remove(profile))
+            .ok_or_else(|| Report::msg(format!("Profile <{}> is not defined", profile)))?;
+
+        Ok(AppConfig {
+            package: package.merge(profile_config).into(),
+            profile: Some(profile.to_owned()),
+        })
     }
 }
 
+const DEFAULT_IGNORE_FILE: &str = ".nextcloudignore";
+const DEFAULT_ARTIFACT_NAME: &str = "{app_id}";
+
 #[derive(Debug)]
 pub struct PackageConfig {
-    before_cmds: Vec<String>,
+    before_cmds: Vec<PackageStep>,
+    after_cmds: Vec<PackageStep>,
+    dependencies: DependencyConfig,
+    limits: LimitsConfig,
+    licenses: LicensesConfig,
+    keep_build_dir_on_failure: bool,
+    ignore_file: String,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    artifact_name: String,
+    keep_artifacts: usize,
+    build_info: bool,
+    code_signing: CodeSigningConfig,
+    verify: bool,
 }
 
 impl PackageConfig {
-    pub fn before_cmds(&self) -> &Vec<String> {
+    pub fn before_cmds(&self) -> &Vec<PackageStep> {
         &self.before_cmds
     }
+
+    pub fn after_cmds(&self) -> &Vec<PackageStep> {
+        &self.after_cmds
+    }
+
+    pub fn dependencies(&self) -> &DependencyConfig {
+        &self.dependencies
+    }
+
+    pub fn limits(&self) -> &LimitsConfig {
+        &self.limits
+    }
+
+    pub fn licenses(&self) -> &LicensesConfig {
+        &self.licenses
+    }
+
+    pub fn keep_build_dir_on_failure(&self) -> bool {
+        self.keep_build_dir_on_failure
+    }
+
+    pub fn ignore_file(&self) -> &String {
+        &self.ignore_file
+    }
+
+    /// Globs of the files to package. If empty, all files are packaged
+    /// that are not excluded.
+    pub fn include(&self) -> &Vec<String> {
+        &self.include
+    }
+
+    pub fn exclude(&self) -> &Vec<String> {
+        &self.exclude
+    }
+
+    pub fn artifact_name(&self) -> &String {
+        &self.artifact_name
+    }
+
+    /// Number of builds to keep in `build/artifacts`, including the new one
+    pub fn keep_artifacts(&self) -> usize {
+        self.keep_artifacts
+    }
+
+    /// Whether to write `appinfo/build-info.json` into the packaged app
+    pub fn build_info(&self) -> bool {
+        self.build_info
+    }
+
+    pub fn code_signing(&self) -> &CodeSigningConfig {
+        &self.code_signing
+    }
+
+    /// Whether to verify the archive before it replaces the previous one
+    pub fn verify(&self) -> bool {
+        self.verify
+    }
 }
 
-impl Into<PackageConfig> for ParsedPackageConfig {
-    fn into(self) -> PackageConfig {
+impl From<ParsedPackageConfig> for PackageConfig {
+    fn from(parsed: ParsedPackageConfig) -> Self {
         PackageConfig {
-            before_cmds: self.before_cmds.unwrap_or(vec![]),
+            before_cmds: parsed
+                .before_cmds
+                .map(|cmds| cmds.into_iter().map(|cmd| cmd.into()).collect())
+                .unwrap_or_default(),
+            after_cmds: parsed
+                .after_cmds
+                .map(|cmds| cmds.into_iter().map(|cmd| cmd.into()).collect())
+                .unwrap_or_default(),
+            dependencies: parsed.dependencies.map(|dc| dc.into()).unwrap_or_default(),
+            limits: parsed.limits.map(|lc| lc.into()).unwrap_or_default(),
+            licenses: parsed.licenses.map(|lc| lc.into()).unwrap_or_default(),
+            keep_build_dir_on_failure: parsed.keep_build_dir_on_failure.unwrap_or(false),
+            ignore_file: parsed
+                .ignore_file
+                .unwrap_or_else(|| DEFAULT_IGNORE_FILE.to_owned()),
+            include: parsed.include.unwrap_or_default(),
+            exclude: parsed.exclude.unwrap_or_default(),
+            artifact_name: parsed
+                .artifact_name
+                .unwrap_or_else(|| DEFAULT_ARTIFACT_NAME.to_owned()),
+            keep_artifacts: parsed.keep_artifacts.unwrap_or(1).max(1),
+            build_info: parsed.build_info.unwrap_or(true),
+            code_signing: parsed.code_signing.map(|cc| cc.into()).unwrap_or_default(),
+            verify: parsed.verify.unwrap_or(false),
         }
     }
 }
 
 impl Default for PackageConfig {
     fn default() -> Self {
-        PackageConfig {
-            before_cmds: vec![],
+        ParsedPackageConfig::default().into()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PackageStep {
+    Command(PackageCommand),
+    /// Commands that run concurrently. If one of them fails, the others
+    /// are killed.
+    Parallel(Vec<PackageCommand>),
+}
+
+impl PackageStep {
+    pub fn commands(&self) -> &[PackageCommand] {
+        match self {
+            PackageStep::Command(cmd) => std::slice::from_ref(cmd),
+            PackageStep::Parallel(cmds) => cmds,
+        }
+    }
+}
+
+impl From<ParsedStep> for PackageStep {
+    fn from(parsed: ParsedStep) -> Self {
+        match parsed {
+            ParsedStep::Command(cmd) => PackageStep::Command(cmd.into()),
+            ParsedStep::Parallel(ParsedParallelStep { parallel }) => {
+                PackageStep::Parallel(parallel.into_iter().map(|cmd| cmd.into()).collect())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageCommand {
+    run: String,
+    name: Option<String>,
+    cwd: Option<PathBuf>,
+    env: BTreeMap<String, String>,
+    timeout: Option<Duration>,
+    allow_failure: bool,
+}
+
+impl PackageCommand {
+    pub fn new<S: Into<String>>(run: S) -> Self {
+        PackageCommand {
+            run: run.into(),
+            name: None,
+            cwd: None,
+            env: BTreeMap::new(),
+            timeout: None,
+            allow_failure: false,
+        }
+    }
+
+    pub fn run(&self) -> &String {
+        &self.run
+    }
+
+    /// Prefix for the command's output, defaults to the command itself
+    pub fn name(&self) -> &String {
+        self.name.as_ref().unwrap_or(&self.run)
+    }
+
+    /// Working directory relative to the app's root directory
+    pub fn cwd(&self) -> Option<&PathBuf> {
+        self.cwd.as_ref()
+    }
+
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn allow_failure(&self) -> bool {
+        self.allow_failure
+    }
+
+    /// Returns a copy with `run` applied to the command and `f` applied to
+    /// its working directory and the values of its environment variables
+    pub fn map_values<R, F>(&self, run: R, f: F) -> Self
+    where
+        R: Fn(&str) -> String,
+        F: Fn(&str) -> String,
+    {
+        PackageCommand {
+            run: run(&self.run),
+            name: self.name.clone(),
+            cwd: self
+                .cwd
+                .as_ref()
+                .map(|cwd| PathBuf::from(f(&cwd.to_string_lossy()))),
+            env: self
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), f(value)))
+                .collect(),
+            timeout: self.timeout,
+            allow_failure: self.allow_failure,
+        }
+    }
+}
+
+impl From<ParsedCommand> for PackageCommand {
+    fn from(parsed: ParsedCommand) -> Self {
+        match parsed {
+            ParsedCommand::Plain(run) => PackageCommand::new(run),
+            ParsedCommand::Structured(cmd) => PackageCommand {
+                run: cmd.run,
+                name: cmd.name,
+                cwd: cmd.cwd,
+                env: cmd.env.unwrap_or_default(),
+                timeout: cmd.timeout.map(Duration::from_secs),
+                allow_failure: cmd.allow_failure.unwrap_or(false),
+            },
+        }
+    }
+}
+
+const DEFAULT_COMPOSER_CMD: &str = "composer install --no-dev";
+const DEFAULT_NPM_CMD: &str = "npm ci";
+
+#[derive(Debug)]
+pub struct DependencyConfig {
+    install: bool,
+    composer_cmd: String,
+    npm_cmd: String,
+}
+
+impl DependencyConfig {
+    pub fn install(&self) -> bool {
+        self.install
+    }
+
+    pub fn composer_cmd(&self) -> &String {
+        &self.composer_cmd
+    }
+
+    pub fn npm_cmd(&self) -> &String {
+        &self.npm_cmd
+    }
+}
+
+impl From<ParsedDependencyConfig> for DependencyConfig {
+    fn from(parsed: ParsedDependencyConfig) -> Self {
+        DependencyConfig {
+            install: parsed.install.unwrap_or(true),
+            composer_cmd: parsed
+                .composer_cmd
+                .unwrap_or_else(|| DEFAULT_COMPOSER_CMD.to_owned()),
+            npm_cmd: parsed.npm_cmd.unwrap_or_else(|| DEFAULT_NPM_CMD.to_owned()),
+        }
+    }
+}
+
+impl Default for DependencyConfig {
+    fn default() -> Self {
+        DependencyConfig {
+            install: true,
+            composer_cmd: DEFAULT_COMPOSER_CMD.to_owned(),
+            npm_cmd: DEFAULT_NPM_CMD.to_owned(),
+        }
+    }
+}
+
+/// Size budgets of the app archive, in bytes
+#[derive(Debug, Default)]
+pub struct LimitsConfig {
+    max_archive_size: Option<u64>,
+    max_file_size: Option<u64>,
+}
+
+impl LimitsConfig {
+    /// Maximum size of the compressed archive
+    pub fn max_archive_size(&self) -> Option<u64> {
+        self.max_archive_size
+    }
+
+    /// Maximum size of any single packaged file
+    pub fn max_file_size(&self) -> Option<u64> {
+        self.max_file_size
+    }
+}
+
+impl From<ParsedLimitsConfig> for LimitsConfig {
+    fn from(parsed: ParsedLimitsConfig) -> Self {
+        LimitsConfig {
+            max_archive_size: parsed.max_archive_size,
+            max_file_size: parsed.max_file_size,
+        }
+    }
+}
+
+/// License policy for third-party packages
+#[derive(Debug, Default)]
+pub struct LicensesConfig {
+    allowed: Vec<String>,
+    check: bool,
+}
+
+impl LicensesConfig {
+    /// SPDX identifiers of the licenses packages may use
+    pub fn allowed(&self) -> &Vec<String> {
+        &self.allowed
+    }
+
+    /// Whether to check the licenses before packaging
+    pub fn check(&self) -> bool {
+        self.check
+    }
+}
+
+impl From<ParsedLicensesConfig> for LicensesConfig {
+    fn from(parsed: ParsedLicensesConfig) -> Self {
+        LicensesConfig {
+            allowed: parsed.allowed.unwrap_or_default(),
+            check: parsed.check.unwrap_or(false),
+        }
+    }
+}
+
+/// Signing of the packaged app for the server's code integrity check
+#[derive(Debug, Default)]
+pub struct CodeSigningConfig {
+    enabled: bool,
+    key: Option<PathBuf>,
+    certificate: Option<PathBuf>,
+}
+
+impl CodeSigningConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Path of the private key, relative to the app
+    pub fn key(&self) -> Option<&PathBuf> {
+        self.key.as_ref()
+    }
+
+    /// Path of the certificate, relative to the app
+    pub fn certificate(&self) -> Option<&PathBuf> {
+        self.certificate.as_ref()
+    }
+}
+
+impl From<ParsedCodeSigningConfig> for CodeSigningConfig {
+    fn from(parsed: ParsedCodeSigningConfig) -> Self {
+        CodeSigningConfig {
+            enabled: parsed.enabled.unwrap_or(false),
+            key: parsed.key,
+            certificate: parsed.certificate,
         }
     }
 }
@@ -121,20 +698,66 @@ where
     reader.read()
 }
 
+/// Dotted TOML key of an ignored value, without serde's `Option` and newtype
+/// wrappers
+fn key_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+
+    let (parent, key) = match path {
+        Path::Root => return String::new(),
+        Path::Seq { parent, index } => (parent, index.to_string()),
+        Path::Map { parent, key } => (parent, key.clone()),
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => return key_path(parent),
+    };
+    match key_path(parent) {
+        parent if parent.is_empty() => key,
+        parent => format!("{}.{}", parent, key),
+    }
+}
+
+/// Parses the config and returns the keys that are ignored because they
+/// are unknown
+fn parse_config_with_unknown_keys(config: &str) -> Result<(ParsedAppConfig, Vec<String>)> {
+    let mut unknown_keys = vec![];
+    let config = serde_ignored::deserialize(toml::Deserializer::new(config), |path| {
+        unknown_keys.push(key_path(&path))
+    })
+    .wrap_err("Failed to parse config as toml")?;
+    Ok((config, unknown_keys))
+}
+
+/// Unknown keys are only warned about, older versions of Krankerl ignored
+/// them silently
 fn parse_config(config: String) -> Result<ParsedAppConfig> {
-    toml::from_str(&config).wrap_err("Failed to parse config as toml")
+    let (config, unknown_keys) = parse_config_with_unknown_keys(&config)?;
+    for key in unknown_keys {
+        println!("Warning: unknown key <{}> in krankerl.toml is ignored", key);
+    }
+    Ok(config)
 }
 
-pub fn get_config(path: &Path) -> Result<Option<AppConfig>> {
+pub fn get_config(path: &Path, profile: Option<&str>) -> Result<Option<AppConfig>> {
     let mut path_buf = path.to_path_buf();
     path_buf.push("krankerl.toml");
     let reader = ConfigFileReader::new(path_buf);
 
     if !reader.has_config() {
-        Ok(None)
+        match profile {
+            Some(profile) => Err(Report::msg(format!(
+                "Profile <{}> is not defined, krankerl.toml is missing",
+                profile
+            ))),
+            None => Ok(None),
+        }
     } else {
         let config_str = load_config(&reader)?;
-        parse_config(config_str).map(|config| Some(config.into()))
+        let config = parse_config(config_str)?;
+        match profile {
+            Some(profile) => config.into_profile(profile).map(Some),
+            None => Ok(Some(config.into())),
+        }
     }
 }
 
@@ -284,4 +907,384 @@ mod tests {
         let cmds = package_config.before_cmds.unwrap();
         assert_eq!(3, cmds.len());
     }
+
+    #[test]
+    fn test_parse_config_with_structured_commands() {
+        let toml = r#"
+        [package]
+        before_cmds = [
+            "composer install",
+            { run = "npm ci", cwd = "js", timeout = 600 },
+            { run = "make", env = { NODE_ENV = "production" }, allow_failure = true },
+        ]"#;
+
+        let config: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+
+        let cmds: Vec<&PackageCommand> = config
+            .package()
+            .before_cmds()
+            .iter()
+            .flat_map(|step| step.commands())
+            .collect();
+        assert_eq!(3, cmds.len());
+        assert_eq!("composer install", cmds[0].run());
+        assert_eq!(None, cmds[0].cwd());
+        assert!(!cmds[0].allow_failure());
+        assert_eq!("npm ci", cmds[1].run());
+        assert_eq!(Some(&PathBuf::from("js")), cmds[1].cwd());
+        assert_eq!(Some(Duration::from_secs(600)), cmds[1].timeout());
+        assert_eq!("production", cmds[2].env()["NODE_ENV"]);
+        assert!(cmds[2].allow_failure());
+    }
+
+    #[test]
+    fn test_parse_config_with_parallel_commands() {
+        let toml = r#"
+        [package]
+        before_cmds = [
+            { parallel = ["composer install", { run = "npm ci", cwd = "js" }] },
+            "npm run build",
+        ]"#;
+
+        let config: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+
+        let steps = config.package().before_cmds();
+        assert_eq!(2, steps.len());
+        match &steps[0] {
+            PackageStep::Parallel(cmds) => {
+                assert_eq!("composer install", cmds[0].run());
+                assert_eq!("npm ci", cmds[1].run());
+            }
+            step => panic!("expected a parallel step, got {:?}", step),
+        }
+        assert!(matches!(steps[1], PackageStep::Command(_)));
+    }
+
+    #[test]
+    fn test_parse_config_with_invalid_structured_command() {
+        let toml = r#"
+        [package]
+        before_cmds = [
+            { cwd = "js" },
+        ]"#;
+
+        assert!(parse_config(toml.to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_parse_config_with_unknown_command_option() {
+        let toml = r#"
+        [package]
+        before_cmds = [
+            { run = "npm ci", timout = 600 },
+        ]"#;
+
+        assert!(parse_config(toml.to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_parse_config_with_after_commands() {
+        let toml = r#"
+        [package]
+        after_cmds = [
+            "sha256sum $KRANKERL_ARTIFACT",
+        ]"#;
+
+        let config: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+
+        assert!(config.package().before_cmds().is_empty());
+        assert_eq!(1, config.package().after_cmds().len());
+    }
+
+    #[test]
+    fn test_parse_config_without_dependencies() {
+        let toml = r#"
+        [package]
+        before_cmds = []"#;
+
+        let config: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+
+        let dependencies = config.package().dependencies();
+        assert!(dependencies.install());
+        assert_eq!("composer install --no-dev", dependencies.composer_cmd());
+        assert_eq!("npm ci", dependencies.npm_cmd());
+    }
+
+    #[test]
+    fn test_parse_config_with_disabled_dependencies() {
+        let toml = r#"
+        [package.dependencies]
+        install = false"#;
+
+        let config: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+
+        assert!(!config.package().dependencies().install());
+    }
+
+    #[test]
+    fn test_parse_config_with_dependency_commands() {
+        let toml = r#"
+        [package.dependencies]
+        composer_cmd = "composer install --no-dev -o"
+        npm_cmd = "npm ci --ignore-scripts""#;
+
+        let config: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+
+        let dependencies = config.package().dependencies();
+        assert!(dependencies.install());
+        assert_eq!("composer install --no-dev -o", dependencies.composer_cmd());
+        assert_eq!("npm ci --ignore-scripts", dependencies.npm_cmd());
+    }
+
+    const PROFILES_CONFIG: &str = r#"
+        [package]
+        before_cmds = ["npm run build"]
+        artifact_name = "{app_id}-{profile}"
+
+        [package.dependencies]
+        npm_cmd = "npm ci --ignore-scripts"
+
+        [package.profiles.dev]
+        before_cmds = ["npm run dev"]
+
+        [package.profiles.shipped]
+        ignore_file = ".nextcloudignore-shipped"
+
+        [package.profiles.shipped.dependencies]
+        install = false"#;
+
+    #[test]
+    fn test_parse_config_without_profile() {
+        let config: AppConfig = parse_config(PROFILES_CONFIG.to_owned()).unwrap().into();
+
+        assert_eq!(None, config.profile());
+        assert_eq!(
+            "npm run build",
+            config.package().before_cmds()[0].commands()[0].run()
+        );
+        assert_eq!(".nextcloudignore", config.package().ignore_file());
+    }
+
+    #[test]
+    fn test_parse_config_with_profile() {
+        let config = parse_config(PROFILES_CONFIG.to_owned())
+            .unwrap()
+            .into_profile("dev")
+            .unwrap();
+
+        assert_eq!(Some(&"dev".to_owned()), config.profile());
+        assert_eq!(
+            "npm run dev",
+            config.package().before_cmds()[0].commands()[0].run()
+        );
+        assert_eq!("{app_id}-{profile}", config.package().artifact_name());
+    }
+
+    #[test]
+    fn test_parse_config_with_profile_dependencies() {
+        let config = parse_config(PROFILES_CONFIG.to_owned())
+            .unwrap()
+            .into_profile("shipped")
+            .unwrap();
+
+        let package = config.package();
+        assert_eq!(
+            "npm run build",
+            package.before_cmds()[0].commands()[0].run()
+        );
+        assert_eq!(".nextcloudignore-shipped", package.ignore_file());
+        assert!(!package.dependencies().install());
+        assert_eq!("npm ci --ignore-scripts", package.dependencies().npm_cmd());
+    }
+
+    #[test]
+    fn test_parse_config_with_profile_file_rules() {
+        let toml = r#"
+        [package]
+        exclude = ["tests/"]
+
+        [package.profiles.minimal]
+        include = ["/appinfo/", "/lib/"]"#;
+
+        let config = parse_config(toml.to_owned()).unwrap();
+        let default: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+        let minimal = config.into_profile("minimal").unwrap();
+
+        assert!(default.package().include().is_empty());
+        assert_eq!(&vec!["tests/".to_owned()], default.package().exclude());
+        assert_eq!(
+            &vec!["/appinfo/".to_owned(), "/lib/".to_owned()],
+            minimal.package().include()
+        );
+        assert_eq!(&vec!["tests/".to_owned()], minimal.package().exclude());
+    }
+
+    #[test]
+    fn test_parse_config_with_artifact_history() {
+        let toml = r#"
+        [package]
+        artifact_name = "{app_id}-{version}"
+        keep_artifacts = 5"#;
+
+        let config: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+        let default: AppConfig = parse_config("[package]".to_owned()).unwrap().into();
+
+        assert_eq!("{app_id}-{version}", config.package().artifact_name());
+        assert_eq!(5, config.package().keep_artifacts());
+        assert_eq!(1, default.package().keep_artifacts());
+    }
+
+    #[test]
+    fn test_parse_config_without_build_info() {
+        let toml = r#"
+        [package.profiles.nightly]
+        build_info = false"#;
+
+        let config = parse_config(toml.to_owned()).unwrap();
+        let default: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+        let nightly = config.into_profile("nightly").unwrap();
+
+        assert!(default.package().build_info());
+        assert!(!nightly.package().build_info());
+    }
+
+    #[test]
+    fn test_parse_config_with_limits() {
+        let toml = r#"
+        [package.limits]
+        max_archive_size = "1.5 MiB"
+        max_file_size = 500000
+
+        [package.profiles.nightly.limits]
+        max_archive_size = "2MB""#;
+
+        let config = parse_config(toml.to_owned()).unwrap();
+        let default: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+        let nightly = config.into_profile("nightly").unwrap();
+
+        assert_eq!(Some(1572864), default.package().limits().max_archive_size());
+        assert_eq!(Some(500000), default.package().limits().max_file_size());
+        assert_eq!(Some(2000000), nightly.package().limits().max_archive_size());
+        assert_eq!(Some(500000), nightly.package().limits().max_file_size());
+    }
+
+    #[test]
+    fn test_parse_config_without_limits() {
+        let config: AppConfig = parse_config("[package]".to_owned()).unwrap().into();
+
+        assert_eq!(None, config.package().limits().max_archive_size());
+        assert_eq!(None, config.package().limits().max_file_size());
+    }
+
+    #[test]
+    fn test_parse_config_with_licenses() {
+        let toml = r#"
+        [package.licenses]
+        allowed = ["MIT", "AGPL-3.0-or-later"]
+
+        [package.profiles.release.licenses]
+        check = true"#;
+
+        let config = parse_config(toml.to_owned()).unwrap();
+        let default: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+        let release = config.into_profile("release").unwrap();
+
+        assert_eq!(
+            &vec!["MIT".to_owned(), "AGPL-3.0-or-later".to_owned()],
+            default.package().licenses().allowed()
+        );
+        assert!(!default.package().licenses().check());
+        assert_eq!(2, release.package().licenses().allowed().len());
+        assert!(release.package().licenses().check());
+    }
+
+    #[test]
+    fn test_parse_config_with_code_signing() {
+        let toml = r#"
+        [package.code_signing]
+        key = "keys/news.key"
+
+        [package.profiles.release.code_signing]
+        enabled = true"#;
+
+        let config = parse_config(toml.to_owned()).unwrap();
+        let default: AppConfig = parse_config(toml.to_owned()).unwrap().into();
+        let release = config.into_profile("release").unwrap();
+
+        assert!(!default.package().code_signing().enabled());
+        let code_signing = release.package().code_signing();
+        assert!(code_signing.enabled());
+        assert_eq!(Some(&PathBuf::from("keys/news.key")), code_signing.key());
+        assert_eq!(None, code_signing.certificate());
+    }
+
+    #[test]
+    fn test_parse_config_with_invalid_limit() {
+        let toml = r#"
+        [package.limits]
+        max_archive_size = "lots""#;
+
+        assert!(parse_config(toml.to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_parse_config_with_invalid_artifact_name() {
+        for name in &["../../x", "/tmp/x", "builds/{app_id}", "..", ""] {
+            let toml = format!("[package]\nartifact_name = {:?}", name);
+
+            assert!(parse_config(toml).is_err(), "{} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_config_with_unknown_profile_key() {
+        let toml = r#"
+        [package.profiles.nightly]
+        artifact_nmae = "{app_id}-nightly"
+
+        [package]
+        legacy = true"#;
+
+        let (config, unknown_keys) = parse_config_with_unknown_keys(toml).unwrap();
+
+        assert_eq!(
+            vec![
+                "package.profiles.nightly.artifact_nmae".to_owned(),
+                "package.legacy".to_owned()
+            ],
+            unknown_keys
+        );
+        assert!(config.into_profile("nightly").is_ok());
+    }
+
+    #[test]
+    fn test_parse_config_with_unknown_parallel_option() {
+        let toml = r#"
+        [package]
+        before_cmds = [
+            { parallel = ["composer install"], timeout = 600 },
+        ]"#;
+
+        assert!(parse_config(toml.to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(Some(12), parse_size("12"));
+        assert_eq!(Some(12), parse_size("12 B"));
+        assert_eq!(Some(2048), parse_size("2KiB"));
+        assert_eq!(Some(2000), parse_size("2 kB"));
+        assert_eq!(Some(1572864), parse_size("1.5M"));
+        assert_eq!(None, parse_size("MiB"));
+        assert_eq!(None, parse_size("12 parsecs"));
+    }
+
+    #[test]
+    fn test_parse_config_with_unknown_profile() {
+        let config = parse_config(PROFILES_CONFIG.to_owned())
+            .unwrap()
+            .into_profile("nightly");
+
+        assert!(config.is_err());
+    }
 }
@@ -12,45 +12,81 @@ const USAGE: &'static str = "
 Krankerl. A CLI helper to manage Nextcloud apps.
 
 Usage:
-  krankerl clean
+  krankerl clean [--build-dirs | --keep=<n>]
   krankerl enable
+  krankerl diff <old> [<new>]
   krankerl disable
   krankerl init
+  krankerl licenses [--profile=<name>]
   krankerl login (--appstore | --github) <token>
-  krankerl package [--shipped]
+  krankerl package [--shipped] [--ref=<ref> | --working-tree [--gitignore]] [--keep-build-dir] [--profile=<name>]
+  krankerl package (--list | --dry-run) [--no-build] [--ref=<ref> | --working-tree [--gitignore]] [--keep-build-dir] [--profile=<name>]
   krankerl publish [--nightly] <url>
   krankerl sign --package
   krankerl up
+  krankerl verify [<archive>]
+  krankerl verify-signature [--package <archive>] --signature=<sig> --cert=<crt>
   krankerl version (major|minor|patch)
   krankerl --version
 
 Options:
-  -h --help     Show this screen.
-  --version     Show version.
+  -h --help          Show this screen.
+  --version          Show version.
+  --ref=<ref>        Package a git tag, branch or commit instead of HEAD.
+  --working-tree     Package the working tree including uncommitted changes.
+  --gitignore        Skip files excluded by .gitignore when copying the working tree.
+  --keep-build-dir   Keep the temporary build directory.
+  --profile=<name>   Package with a profile of krankerl.toml.
+  --list             List the files that would be packaged.
+  --dry-run          Same as --list.
+  --no-build         Skip dependency installation and build commands.
+  --build-dirs       Remove kept build directories instead of artifacts.
+  --keep=<n>         Only remove artifacts older than the newest <n> builds.
+  --signature=<sig>  Package signature, or a file containing it.
+  --cert=<crt>       Certificate to verify the package signature with.
 ";
 
 #[derive(Debug, Deserialize)]
 struct Args {
     arg_token: Option<String>,
+    arg_archive: Option<String>,
+    arg_new: Option<String>,
+    arg_old: Option<String>,
     arg_url: Option<String>,
     cmd_clean: bool,
+    cmd_diff: bool,
     cmd_enable: bool,
     cmd_disable: bool,
     cmd_init: bool,
+    cmd_licenses: bool,
     cmd_login: bool,
     cmd_package: bool,
     cmd_publish: bool,
     cmd_sign: bool,
     cmd_up: bool,
+    cmd_verify: bool,
+    cmd_verify_signature: bool,
     cmd_version: bool,
     cmd_major: bool,
     cmd_minor: bool,
     flag_appstore: bool,
+    flag_build_dirs: bool,
+    flag_cert: Option<String>,
+    flag_dry_run: bool,
+    flag_gitignore: bool,
     flag_github: bool,
+    flag_keep: Option<usize>,
+    flag_keep_build_dir: bool,
+    flag_list: bool,
+    flag_no_build: bool,
     flag_nightly: bool,
     flag_package: bool,
+    flag_profile: Option<String>,
+    flag_ref: Option<String>,
     flag_shipped: bool,
+    flag_signature: Option<String>,
     flag_version: bool,
+    flag_working_tree: bool,
 }
 
 #[tokio::main]
@@ -70,8 +106,24 @@ async fn main() -> Result<()> {
         krankerl::commands::init(&cwd).wrap_err("could not create krankerl.toml")?;
         println!("krankerl.toml created.");
     } else if args.cmd_clean {
-        let cwd = PathBuf::from(".");
-        krankerl::commands::clean(&cwd)?;
+        let cwd = Path::new(".");
+        if args.flag_build_dirs {
+            krankerl::commands::clean_build_dirs(cwd)?;
+        } else if let Some(keep) = args.flag_keep {
+            krankerl::commands::clean_artifacts(cwd, keep)?;
+        } else {
+            krankerl::commands::clean(cwd)?;
+        }
+    } else if args.cmd_licenses {
+        krankerl::commands::licenses(Path::new("."), args.flag_profile.as_deref())?;
+    } else if args.cmd_diff {
+        let old = args.arg_old.unwrap();
+        krankerl::commands::diff_archives(
+            Path::new("."),
+            Path::new(&old),
+            args.arg_new.as_ref().map(Path::new),
+        )
+        .wrap_err("could not compare app archives")?;
     } else if args.cmd_login {
         if args.flag_appstore {
             let token = args.arg_token.unwrap();
@@ -82,7 +134,17 @@ async fn main() -> Result<()> {
             krankerl::commands::log_in_to_github(&token).wrap_err("could not save github token")?;
         }
     } else if args.cmd_package {
-        krankerl::commands::package_app(&PathBuf::from("."), args.flag_shipped)
+        let options = krankerl::packaging::PackageOptions {
+            shipped: args.flag_shipped,
+            git_ref: args.flag_ref,
+            working_tree: args.flag_working_tree,
+            gitignore: args.flag_gitignore,
+            keep_build_dir: args.flag_keep_build_dir,
+            profile: args.flag_profile,
+            list: args.flag_list || args.flag_dry_run,
+            skip_build: args.flag_no_build,
+        };
+        krankerl::commands::package_app(Path::new("."), options)
             .wrap_err("could not package app")?;
     } else if args.cmd_publish {
         let url = args.arg_url.unwrap();
@@ -107,6 +169,20 @@ async fn main() -> Result<()> {
     } else if args.cmd_up {
         let cwd = PathBuf::from(".");
         krankerl::commands::up(&cwd)?;
+    } else if args.cmd_verify {
+        krankerl::commands::verify_archive(
+            Path::new("."),
+            args.arg_archive.as_ref().map(Path::new),
+        )
+        .wrap_err("could not verify app archive")?;
+    } else if args.cmd_verify_signature {
+        krankerl::commands::verify_signature(
+            Path::new("."),
+            args.arg_archive.as_ref().map(Path::new),
+            &args.flag_signature.unwrap(),
+            Path::new(&args.flag_cert.unwrap()),
+        )
+        .wrap_err("could not verify package signature")?;
     } else if args.cmd_version {
         let bump = if args.cmd_major {
             "major"
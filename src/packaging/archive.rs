@@ -1,43 +1,110 @@
-use std::fs::File;
+use std::cmp::min;
+use std::fs::{File, Metadata};
 use std::io;
-use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 use std::vec::Vec;
 
 use color_eyre::eyre::WrapErr;
 use color_eyre::Result;
 use ignore::DirEntry;
 use pathdiff::diff_paths;
-use tar::Builder;
+use tar::{Builder, Header};
 
+use crate::packaging::manifest::{HashingReader, ManifestEntry};
+
+/// Collects the files to package as pairs of their path on disk and in the
+/// archive, sorted by the latter so the archive layout does not depend on
+/// the order of the directory walk
+fn sorted_entries(root: &Path, app_path: &Path, files: Vec<DirEntry>) -> Vec<(PathBuf, PathBuf)> {
+    let mut entries: Vec<(PathBuf, PathBuf)> = files
+        .into_iter()
+        .filter(|entry| !entry.metadata().unwrap().is_dir())
+        .filter_map(|entry| {
+            diff_paths(entry.path(), app_path)
+                .map(|normalized| (entry.path().to_path_buf(), root.join(normalized)))
+        })
+        .collect();
+    entries.sort_by(|a, b| a.1.cmp(&b.1));
+    entries
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+/// There is no executable bit to carry over, all files get the default mode
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// Builds a tar header that only depends on the file's content, size and
+/// executable bit. Ownership is dropped and the modification time is
+/// clamped to `mtime` so that rebuilding the same commit yields the very
+/// same archive.
+fn normalized_header(file: &File, mtime: u64) -> Result<Header> {
+    let metadata = file.metadata()?;
+    let file_mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mode = if is_executable(&metadata) {
+        0o755
+    } else {
+        0o644
+    };
+
+    let mut header = Header::new_gnu();
+    header.set_size(metadata.len());
+    header.set_mode(mode);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_username("")?;
+    header.set_groupname("")?;
+    header.set_mtime(min(file_mtime, mtime));
+    Ok(header)
+}
+
+/// Writes the files to a tar archive. Returns the manifest entries of all
+/// packaged files along with the destination.
 pub fn build_app_archive<W>(
     root: &Path,
     app_path: &Path,
     files: Vec<DirEntry>,
+    mtime: u64,
     dest: W,
-) -> Result<W>
+) -> Result<(W, Vec<ManifestEntry>)>
 where
     W: io::Write,
 {
     let mut archive = Builder::new(dest);
+    let mut manifest = vec![];
+
+    for (entry_path, file_path) in sorted_entries(root, app_path, files) {
+        let file = File::open(&entry_path).wrap_err_with(|| {
+            format!(
+                "Failed to open {} for packaging",
+                file_path.to_string_lossy()
+            )
+        })?;
+        let mut header = normalized_header(&file, mtime)?;
+        let mut reader = HashingReader::new(file);
+        archive.append_data(&mut header, &file_path, &mut reader)?;
 
-    for entry in files {
-        if !entry.metadata().unwrap().is_dir() {
-            let entry_path = entry.path();
-            if let Some(normalized) = diff_paths(&entry_path, &app_path) {
-                let mut file_path = root.to_path_buf();
-                file_path.push(&normalized);
-                let mut file = File::open(&entry_path).wrap_err_with(|| {
-                    format!(
-                        "Failed to open {} for packaging",
-                        file_path.to_string_lossy()
-                    )
-                })?;
-                archive.append_file(file_path.as_path(), &mut file)?;
-            }
-        }
+        let (size, sha256) = reader.finish();
+        manifest.push(ManifestEntry {
+            path: file_path.to_string_lossy().into_owned(),
+            size,
+            sha256,
+        });
     }
 
     let dest = archive.into_inner()?;
 
-    Ok(dest)
+    Ok((dest, manifest))
 }
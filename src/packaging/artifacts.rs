@@ -1,12 +1,20 @@
+use std::env;
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use color_eyre::{Report, Result};
 use git2;
+use ignore::WalkBuilder;
+use pathdiff::diff_paths;
+use tempdir::TempDir;
 
 use color_eyre::eyre::WrapErr;
 
+use crate::packaging::commands;
+use crate::packaging::{manifest, sbom};
+
 pub fn clone_app(src: &Path, dst: &Path) -> Result<()> {
     git2::Repository::clone(
         src.as_os_str()
@@ -18,13 +26,383 @@ pub fn clone_app(src: &Path, dst: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn clear(path: &Path) -> Result<()> {
-    if let Err(e) = fs::remove_dir_all(path) {
+/// Recreates the symlink with the same target, like git checks symlinks
+/// out. Symlinked directories such as `vendor` are not copied.
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dst: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dst)
+        .wrap_err_with(|| format!("Failed to copy symlink {}", src.to_string_lossy()))
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(src: &Path, _dst: &Path) -> Result<()> {
+    println!("Skipping symlink {}", src.to_string_lossy());
+    Ok(())
+}
+
+/// Copies the app's working tree including uncommitted changes. The git
+/// metadata, previous build artifacts and the packaging log are left out.
+pub fn copy_working_tree(src: &Path, dst: &Path, gitignore: bool) -> Result<()> {
+    let artifacts_path = src.join("build").join("artifacts");
+    let log_path = commands::log_path(src);
+    let walker = WalkBuilder::new(src)
+        .standard_filters(false)
+        .git_ignore(gitignore)
+        .git_exclude(gitignore)
+        .filter_entry(move |entry| {
+            entry.file_name() != ".git"
+                && entry.path() != artifacts_path
+                && entry.path() != log_path
+        })
+        .build();
+
+    for entry in walker {
+        let entry = entry?;
+        if entry.metadata()?.is_dir() {
+            continue;
+        }
+        if let Some(normalized) = diff_paths(entry.path(), src) {
+            let file_path = dst.join(normalized);
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if entry.path_is_symlink() {
+                copy_symlink(entry.path(), &file_path)?;
+            } else {
+                fs::copy(entry.path(), &file_path).wrap_err_with(|| {
+                    format!("Failed to copy {}", entry.path().to_string_lossy())
+                })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Submodules with SSH URLs authenticate with the keys of the running
+/// ssh-agent. Other credentials aren't supported.
+fn submodule_update_options<'a>() -> git2::SubmoduleUpdateOptions<'a> {
+    let mut tried_agent = false;
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(move |_url, username, allowed| {
+        let username = username.unwrap_or("git");
+        if allowed.contains(git2::CredentialType::USERNAME) {
+            return git2::Cred::username(username);
+        }
+        // libgit2 asks again if the agent's keys are rejected
+        if allowed.contains(git2::CredentialType::SSH_KEY) && !tried_agent {
+            tried_agent = true;
+            return git2::Cred::ssh_key_from_agent(username);
+        }
+        Err(git2::Error::from_str(
+            "No credentials, only SSH keys of the ssh-agent are supported",
+        ))
+    });
+    let mut fetch = git2::FetchOptions::new();
+    fetch.remote_callbacks(callbacks);
+    let mut options = git2::SubmoduleUpdateOptions::new();
+    options.fetch(fetch);
+    options
+}
+
+/// Initializes and updates all submodules of a cloned app recursively so
+/// they match the commits pinned by the app
+pub fn update_submodules(repo_path: &Path) -> Result<()> {
+    let repo = git2::Repository::open(repo_path)?;
+    for mut submodule in repo.submodules()? {
+        let path = repo_path.join(submodule.path());
+        println!("Updating submodule {:?}", submodule.path());
+        submodule
+            .update(true, Some(&mut submodule_update_options()))
+            .wrap_err_with(|| format!("Failed to update submodule {:?}", submodule.path()))?;
+        update_submodules(&path)?;
+    }
+    Ok(())
+}
+
+/// Checks out a tag, branch or commit in a cloned app. Branches of the
+/// source repository are only known as remote branches in the clone.
+pub fn checkout(app_path: &Path, git_ref: &str) -> Result<git2::Oid> {
+    let repo = git2::Repository::open(app_path)?;
+    let object = repo
+        .revparse_single(git_ref)
+        .or_else(|_| repo.revparse_single(&format!("origin/{}", git_ref)))
+        .wrap_err_with(|| format!("Unknown git ref <{}>", git_ref))?;
+    let commit = object.peel_to_commit()?;
+
+    let mut checkout = git2::build::CheckoutBuilder::new();
+    checkout.force();
+    repo.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+    repo.set_head_detached(commit.id())?;
+
+    Ok(commit.id())
+}
+
+/// Timestamp used for all packaged files. Honours `SOURCE_DATE_EPOCH` and
+/// falls back to the commit time of the cloned HEAD. Copied working trees
+/// have no commit, so their files keep their own timestamps.
+pub fn source_date_epoch(app_path: &Path) -> Result<u64> {
+    if let Ok(epoch) = env::var("SOURCE_DATE_EPOCH") {
+        return epoch
+            .parse()
+            .wrap_err("SOURCE_DATE_EPOCH is not a valid timestamp");
+    }
+
+    if !app_path.join(".git").exists() {
+        return Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+    }
+    let repo = git2::Repository::open(app_path)?;
+    let commit = repo.head()?.peel_to_commit()?;
+    Ok(commit.time().seconds().max(0) as u64)
+}
+
+/// Returns the id of the commit checked out at the given path, if it is a
+/// git repository
+pub fn head_commit(repo_path: &Path) -> Result<Option<git2::Oid>> {
+    if !repo_path.join(".git").exists() {
+        return Ok(None);
+    }
+    let repo = git2::Repository::open(repo_path)?;
+    let commit = repo.head()?.peel_to_commit()?;
+    Ok(Some(commit.id()))
+}
+
+/// Creates a staging directory next to the artifacts directory. New
+/// artifacts are written there first and only replace the previous ones
+/// once they are complete. The staging directory is removed if it is
+/// dropped before.
+pub fn stage(artifacts_path: &Path) -> Result<TempDir> {
+    let build_path = artifacts_path
+        .parent()
+        .ok_or_else(|| Report::msg("Invalid artifacts directory"))?;
+    fs::create_dir_all(build_path).wrap_err("Failed to create build directory")?;
+    TempDir::new_in(build_path, ".artifacts").wrap_err("Failed to create staging directory")
+}
+
+/// Replaces the artifacts directory with the staged artifacts
+pub fn replace(staging: TempDir, artifacts_path: &Path) -> Result<()> {
+    let build_path = artifacts_path
+        .parent()
+        .ok_or_else(|| Report::msg("Invalid artifacts directory"))?;
+    let previous = TempDir::new_in(build_path, ".artifacts-previous")?;
+    let previous_path = previous.path().join("artifacts");
+
+    if let Err(e) = fs::rename(artifacts_path, &previous_path) {
         // We can safely ignore NotFound errors here
         if e.kind() != io::ErrorKind::NotFound {
-            return Err(e).wrap_err("Failed to delete artifacts directory");
+            return Err(e).wrap_err("Failed to move previous artifacts");
         }
     }
-    fs::create_dir_all(path)?;
+    if let Err(e) = fs::rename(staging.path(), artifacts_path) {
+        if previous_path.exists() {
+            fs::rename(&previous_path, artifacts_path)
+                .wrap_err("Failed to restore previous artifacts")?;
+        }
+        return Err(e).wrap_err("Failed to move artifacts into place");
+    }
+
     Ok(())
 }
+
+/// An app archive in the artifacts directory together with the files that
+/// were written for it, like its manifest and checksums
+pub struct Build {
+    archive: PathBuf,
+    files: Vec<PathBuf>,
+    modified: SystemTime,
+}
+
+impl Build {
+    pub fn archive(&self) -> &PathBuf {
+        &self.archive
+    }
+}
+
+fn belongs_to(file_name: &str, archive_name: &str) -> bool {
+    file_name == archive_name
+        || file_name == manifest::manifest_name(archive_name)
+        || file_name == sbom::sbom_name(archive_name)
+        || file_name.starts_with(&format!("{}.", archive_name))
+}
+
+/// Lists the builds in the artifacts directory, newest first
+pub fn builds(artifacts_path: &Path) -> Result<Vec<Build>> {
+    if !artifacts_path.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut file_names = vec![];
+    for entry in fs::read_dir(artifacts_path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            file_names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    let mut builds = vec![];
+    for archive_name in file_names.iter().filter(|name| name.ends_with(".tar.gz")) {
+        let archive = artifacts_path.join(archive_name);
+        builds.push(Build {
+            modified: archive.metadata()?.modified()?,
+            files: file_names
+                .iter()
+                .filter(|name| belongs_to(name, archive_name))
+                .map(|name| artifacts_path.join(name))
+                .collect(),
+            archive,
+        });
+    }
+    builds.sort_by(|a, b| b.modified.cmp(&a.modified).then(b.archive.cmp(&a.archive)));
+    Ok(builds)
+}
+
+/// Links the files into the directory without touching their modification
+/// time, which orders the builds
+fn link_into(files: &[PathBuf], dir: &Path) -> Result<()> {
+    for file in files {
+        let file_name = file
+            .file_name()
+            .ok_or_else(|| Report::msg("Invalid artifact path"))?;
+        let target = dir.join(file_name);
+        if fs::hard_link(file, &target).is_err() {
+            fs::copy(file, &target)?;
+            fs::File::options()
+                .write(true)
+                .open(&target)?
+                .set_modified(file.metadata()?.modified()?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Carries the newest `count` previous builds over into the staging
+/// directory. Builds that are overwritten by the staged one don't count.
+pub fn keep_previous(artifacts_path: &Path, staging: &Path, count: usize) -> Result<()> {
+    let previous = builds(artifacts_path)?
+        .into_iter()
+        .filter(|build| {
+            build
+                .archive
+                .file_name()
+                .map(|name| !staging.join(name).exists())
+                .unwrap_or(false)
+        })
+        .take(count);
+
+    for build in previous {
+        link_into(&build.files, staging)
+            .wrap_err_with(|| format!("Failed to keep previous build {:?}", build.archive))?;
+    }
+    Ok(())
+}
+
+/// Removes all but the newest `keep` builds. Returns the number of removed
+/// builds.
+pub fn prune(artifacts_path: &Path, keep: usize) -> Result<usize> {
+    let outdated: Vec<Build> = builds(artifacts_path)?.into_iter().skip(keep).collect();
+    for build in &outdated {
+        for file in &build.files {
+            fs::remove_file(file).wrap_err_with(|| format!("Failed to remove {:?}", file))?;
+        }
+    }
+    Ok(outdated.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn write_build(dir: &Path, archive_name: &str, age: u64) {
+        let files = [
+            archive_name.to_owned(),
+            manifest::manifest_name(archive_name),
+            format!("{}.sha256", archive_name),
+        ];
+        let modified = SystemTime::now() - Duration::from_secs(age);
+        for name in &files {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        fs::File::options()
+            .write(true)
+            .open(dir.join(archive_name))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn lists_builds_newest_first() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        write_build(tmp.path(), "news-1.0.tar.gz", 20);
+        write_build(tmp.path(), "news-1.0.1.tar.gz", 10);
+
+        let builds = builds(tmp.path()).unwrap();
+
+        assert_eq!(2, builds.len());
+        assert_eq!(tmp.path().join("news-1.0.1.tar.gz"), builds[0].archive);
+        assert_eq!(3, builds[0].files.len());
+        assert_eq!(3, builds[1].files.len());
+    }
+
+    #[test]
+    fn keeps_previous_builds() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let artifacts = tmp.path().join("artifacts");
+        let staging = tmp.path().join("staging");
+        fs::create_dir(&artifacts).unwrap();
+        fs::create_dir(&staging).unwrap();
+        write_build(&artifacts, "news-1.0.tar.gz", 30);
+        write_build(&artifacts, "news-1.1.tar.gz", 20);
+        write_build(&artifacts, "news-1.2.tar.gz", 10);
+        write_build(&staging, "news-1.2.tar.gz", 0);
+
+        keep_previous(&artifacts, &staging, 1).unwrap();
+
+        assert_eq!(
+            vec![
+                "news-1.1.manifest.json",
+                "news-1.1.tar.gz",
+                "news-1.1.tar.gz.sha256",
+                "news-1.2.manifest.json",
+                "news-1.2.tar.gz",
+                "news-1.2.tar.gz.sha256",
+            ],
+            file_names(&staging)
+        );
+        let builds = builds(&staging).unwrap();
+        assert_eq!(staging.join("news-1.2.tar.gz"), builds[0].archive);
+    }
+
+    #[test]
+    fn prunes_old_builds() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        write_build(tmp.path(), "news-1.0.tar.gz", 30);
+        write_build(tmp.path(), "news-1.1.tar.gz", 20);
+        write_build(tmp.path(), "news-1.2.tar.gz", 10);
+        fs::create_dir(tmp.path().join("news")).unwrap();
+
+        assert_eq!(2, prune(tmp.path(), 1).unwrap());
+
+        assert_eq!(
+            vec![
+                "news",
+                "news-1.2.manifest.json",
+                "news-1.2.tar.gz",
+                "news-1.2.tar.gz.sha256",
+            ],
+            file_names(tmp.path())
+        );
+        assert_eq!(0, prune(tmp.path(), 1).unwrap());
+    }
+}
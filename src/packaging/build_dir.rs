@@ -0,0 +1,163 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use tempdir::TempDir;
+
+const PREFIX: &str = "krankerl";
+/// Written to kept build directories. Contains the path of the app they
+/// were built from.
+const MARKER: &str = ".krankerl-build";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeepPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// Temporary directory the app is cloned to and built in. Depending on the
+/// keep policy it is deleted or persisted once the packaging pipeline is
+/// done with it.
+pub struct BuildDir {
+    dir: Option<TempDir>,
+    source_path: PathBuf,
+    keep: KeepPolicy,
+    succeeded: bool,
+}
+
+impl BuildDir {
+    pub fn new(source_path: &Path, keep: KeepPolicy) -> Result<Self> {
+        Ok(BuildDir {
+            dir: Some(TempDir::new(PREFIX)?),
+            source_path: source_path.canonicalize()?,
+            keep,
+            succeeded: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.as_ref().unwrap().path()
+    }
+
+    pub fn keep_on_failure(&mut self) {
+        if self.keep == KeepPolicy::Never {
+            self.keep = KeepPolicy::OnFailure;
+        }
+    }
+
+    pub fn succeed(&mut self) {
+        self.succeeded = true;
+    }
+
+    fn persist(&mut self) -> Result<PathBuf> {
+        let path = self.dir.take().unwrap().into_path();
+        fs::write(
+            path.join(MARKER),
+            self.source_path.to_string_lossy().as_bytes(),
+        )?;
+        Ok(path)
+    }
+}
+
+impl Drop for BuildDir {
+    fn drop(&mut self) {
+        let keep = match self.keep {
+            KeepPolicy::Never => false,
+            KeepPolicy::OnFailure => !self.succeeded,
+            KeepPolicy::Always => true,
+        };
+        if !keep {
+            return;
+        }
+
+        match self.persist() {
+            Ok(path) => println!("Build directory kept at {:?}", path),
+            Err(e) => eprintln!("Failed to keep build directory: {}", e),
+        }
+    }
+}
+
+/// Removes all build directories that were kept for the given app
+pub fn remove_kept(app_path: &Path) -> Result<usize> {
+    let app_path = app_path.canonicalize()?;
+    let mut removed = 0;
+
+    for entry in fs::read_dir(env::temp_dir())? {
+        let path = entry?.path();
+        let is_build_dir = path
+            .file_name()
+            .map(|name| name.to_string_lossy().starts_with(PREFIX))
+            .unwrap_or(false);
+        if !is_build_dir {
+            continue;
+        }
+        let built_from = match fs::read_to_string(path.join(MARKER)) {
+            Ok(built_from) => built_from,
+            Err(_) => continue,
+        };
+        if Path::new(&built_from) == app_path {
+            fs::remove_dir_all(&path)
+                .wrap_err_with(|| format!("Failed to remove build directory {:?}", path))?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deletes_dir_by_default() {
+        let app = TempDir::new("krankerl-test").unwrap();
+        let build_dir = BuildDir::new(app.path(), KeepPolicy::Never).unwrap();
+        let path = build_dir.path().to_path_buf();
+
+        drop(build_dir);
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keeps_failed_build_dir() {
+        let app = TempDir::new("krankerl-test").unwrap();
+        let build_dir = BuildDir::new(app.path(), KeepPolicy::OnFailure).unwrap();
+        let path = build_dir.path().to_path_buf();
+
+        drop(build_dir);
+
+        assert!(path.exists());
+        assert_eq!(1, remove_kept(app.path()).unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn deletes_succeeded_build_dir() {
+        let app = TempDir::new("krankerl-test").unwrap();
+        let mut build_dir = BuildDir::new(app.path(), KeepPolicy::OnFailure).unwrap();
+        let path = build_dir.path().to_path_buf();
+
+        build_dir.succeed();
+        drop(build_dir);
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keeps_build_dir() {
+        let app = TempDir::new("krankerl-test").unwrap();
+        let mut build_dir = BuildDir::new(app.path(), KeepPolicy::Always).unwrap();
+        let path = build_dir.path().to_path_buf();
+
+        build_dir.succeed();
+        drop(build_dir);
+
+        assert!(path.exists());
+        assert_eq!(1, remove_kept(app.path()).unwrap());
+    }
+}
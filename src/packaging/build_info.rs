@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use git2::{Repository, StatusOptions};
+
+use super::artifacts;
+
+/// Location of the build info inside the packaged app
+pub const BUILD_INFO_PATH: &str = "appinfo/build-info.json";
+
+/// Describes which commit and tool produced an app archive
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub git_commit: Option<String>,
+    /// Tag pointing at the packaged commit
+    pub git_tag: Option<String>,
+    /// Whether the app was packaged with uncommitted changes
+    pub dirty: bool,
+    /// Time of the packaged commit, or `SOURCE_DATE_EPOCH` if set. Apps
+    /// that aren't git repositories use the time they are packaged.
+    pub committed_at: String,
+    pub krankerl_version: String,
+    pub profile: Option<String>,
+}
+
+/// Commit time as RFC 3339 timestamp. Uses the same timestamp as the
+/// packaged files to keep archives reproducible.
+fn committed_at(repo_path: &Path) -> Result<String> {
+    let seconds = artifacts::source_date_epoch(repo_path)?;
+    Ok(Utc
+        .timestamp(seconds as i64, 0)
+        .to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+fn head_tag(repo: &Repository) -> Result<Option<String>> {
+    let head = repo.head()?.peel_to_commit()?.id();
+    let mut tags = vec![];
+    for name in repo.tag_names(None)?.iter().flatten() {
+        // Tags may point at trees or blobs, those never tag the commit
+        let commit = match repo
+            .revparse_single(&format!("refs/tags/{}", name))
+            .and_then(|object| object.peel_to_commit())
+        {
+            Ok(commit) => commit,
+            Err(_) => continue,
+        };
+        if commit.id() == head {
+            tags.push(name.to_owned());
+        }
+    }
+    tags.sort();
+    Ok(tags.pop())
+}
+
+/// Uncommitted changes to tracked files make a working tree dirty, like
+/// `git describe --dirty` does
+fn is_dirty(repo: &Repository) -> Result<bool> {
+    let statuses = repo.statuses(Some(
+        StatusOptions::new()
+            .include_untracked(false)
+            .include_ignored(false),
+    ))?;
+    Ok(!statuses.is_empty())
+}
+
+impl BuildInfo {
+    /// Reads the git data of the repository the app was packaged from.
+    /// Only working trees can be dirty, clones are always clean.
+    pub fn new(repo_path: &Path, working_tree: bool, profile: Option<&String>) -> Result<Self> {
+        let mut info = BuildInfo {
+            git_commit: None,
+            git_tag: None,
+            dirty: false,
+            committed_at: committed_at(repo_path)?,
+            krankerl_version: env!("CARGO_PKG_VERSION").to_owned(),
+            profile: profile.cloned(),
+        };
+
+        if repo_path.join(".git").exists() {
+            let repo = Repository::open(repo_path)?;
+            info.git_commit = Some(repo.head()?.peel_to_commit()?.id().to_string());
+            info.git_tag = head_tag(&repo)?;
+            info.dirty = working_tree && is_dirty(&repo)?;
+        }
+        Ok(info)
+    }
+
+    /// Writes the build info into the app at the given path
+    pub fn write(&self, app_path: &Path) -> Result<()> {
+        let path = app_path.join(BUILD_INFO_PATH);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(&path, json + "\n")
+            .wrap_err_with(|| format!("Failed to write build info {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn commit_file(repo: &Repository, name: &str, content: &str) -> git2::Oid {
+        let workdir = repo.workdir().unwrap();
+        fs::write(workdir.join(name), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "test",
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_git_data() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let repo = Repository::init(tmp.path()).unwrap();
+        let commit = commit_file(&repo, "a.txt", "a");
+        repo.tag_lightweight("v1.0.0", &repo.find_object(commit, None).unwrap(), false)
+            .unwrap();
+
+        let info = BuildInfo::new(tmp.path(), false, Some(&"nightly".to_owned())).unwrap();
+
+        assert_eq!(Some(commit.to_string()), info.git_commit);
+        assert_eq!(Some("v1.0.0".to_owned()), info.git_tag);
+        assert!(!info.dirty);
+        let time = repo.find_commit(commit).unwrap().time().seconds();
+        assert_eq!(
+            Utc.timestamp(time, 0)
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            info.committed_at
+        );
+        assert_eq!(Some("nightly".to_owned()), info.profile);
+        assert_eq!(env!("CARGO_PKG_VERSION"), info.krankerl_version);
+    }
+
+    #[test]
+    fn ignores_tags_of_trees() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let repo = Repository::init(tmp.path()).unwrap();
+        let commit = commit_file(&repo, "a.txt", "a");
+        let tree = repo.find_commit(commit).unwrap().tree().unwrap();
+        repo.tag_lightweight("a-tree", tree.as_object(), false)
+            .unwrap();
+
+        let info = BuildInfo::new(tmp.path(), false, None).unwrap();
+
+        assert_eq!(None, info.git_tag);
+    }
+
+    #[test]
+    fn detects_dirty_working_tree() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let repo = Repository::init(tmp.path()).unwrap();
+        commit_file(&repo, "a.txt", "a");
+        fs::write(tmp.path().join("untracked.txt"), "").unwrap();
+
+        assert!(!BuildInfo::new(tmp.path(), true, None).unwrap().dirty);
+
+        fs::write(tmp.path().join("a.txt"), "changed").unwrap();
+
+        let info = BuildInfo::new(tmp.path(), true, None).unwrap();
+        assert!(info.dirty);
+        assert_eq!(None, info.git_tag);
+        assert!(!BuildInfo::new(tmp.path(), false, None).unwrap().dirty);
+    }
+
+    #[test]
+    fn writes_build_info() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+
+        let info = BuildInfo::new(tmp.path(), false, None).unwrap();
+        info.write(tmp.path()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(tmp.path().join("appinfo").join("build-info.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(serde_json::Value::Null, json["git_commit"]);
+        assert_eq!(false, json["dirty"]);
+        assert!(json["committed_at"].as_str().unwrap().ends_with('Z'));
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Report, Result};
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, RsaPrivateKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha512};
+
+use super::package_signature;
+
+/// Location of the signature inside the app, as expected by the server
+pub const SIGNATURE_PATH: &str = "appinfo/signature.json";
+
+/// Files the server skips when it checks the integrity of an app
+const EXCLUDED_FILE_NAMES: [&str; 4] = [".DS_Store", "Thumbs.db", ".directory", ".webapp"];
+
+/// Matches `.webapp-nextcloud-<major>.<minor>.<patch>[-r<revision>]`, which
+/// Gentoo's webapp-config writes and the server skips as well
+fn is_webapp_config_file(file_name: &str) -> bool {
+    let is_number = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let version = match file_name.strip_prefix(".webapp-nextcloud-") {
+        Some(version) => version,
+        None => return false,
+    };
+    let version = match version.split_once("-r") {
+        Some((version, revision)) if is_number(revision) => version,
+        Some(_) => return false,
+        None => version,
+    };
+    let parts: Vec<&str> = version.split('.').collect();
+    parts.len() == 3 && parts.into_iter().all(is_number)
+}
+
+/// Contents of `appinfo/signature.json`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Signature {
+    pub hashes: BTreeMap<String, String>,
+    pub signature: String,
+    pub certificate: String,
+}
+
+/// Hex encoded SHA-512 hashes of the given files, by path relative to the
+/// app. The signature file itself and files the server ignores are left
+/// out.
+pub fn hash_files(app_path: &Path, files: &[PathBuf]) -> Result<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+    for file in files {
+        let name = file
+            .iter()
+            .map(|component| component.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let file_name = file
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        if name == SIGNATURE_PATH
+            || EXCLUDED_FILE_NAMES.contains(&&*file_name)
+            || is_webapp_config_file(&file_name)
+        {
+            continue;
+        }
+
+        let mut hasher = Sha512::new();
+        io::copy(&mut File::open(app_path.join(file))?, &mut hasher)
+            .wrap_err_with(|| format!("Failed to hash {}", name))?;
+        hashes.insert(name, hex::encode(hasher.finalize()));
+    }
+    Ok(hashes)
+}
+
+fn php_json_string(text: &str, json: &mut String) {
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '/' => json.push_str("\\/"),
+            '\u{8}' => json.push_str("\\b"),
+            '\u{c}' => json.push_str("\\f"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_ascii() && !c.is_ascii_control() => json.push(c),
+            c => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    json.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    json.push('"');
+}
+
+/// The signed message is the hashes encoded like PHP's `json_encode` does,
+/// which escapes slashes and all non-ASCII characters
+pub fn signed_message(hashes: &BTreeMap<String, String>) -> String {
+    let mut json = String::from("{");
+    for (i, (name, hash)) in hashes.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        php_json_string(name, &mut json);
+        json.push(':');
+        php_json_string(hash, &mut json);
+    }
+    json.push('}');
+    json
+}
+
+fn mgf1_sha512(seed: &[u8], length: usize) -> Vec<u8> {
+    let mut mask = Vec::with_capacity(length + 64);
+    let mut counter: u32 = 0;
+    while mask.len() < length {
+        let mut hasher = Sha512::new();
+        hasher.update(seed);
+        hasher.update(counter.to_be_bytes());
+        mask.extend_from_slice(&hasher.finalize());
+        counter += 1;
+    }
+    mask.truncate(length);
+    mask
+}
+
+/// The server signs with RSASSA-PSS using SHA-1 for the message, MGF1 with
+/// SHA-512 and no salt, see RFC 8017 section 9.1
+fn pss_hash(message_hash: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    hasher.update([0u8; 8]);
+    hasher.update(message_hash);
+    hasher.finalize().to_vec()
+}
+
+fn pss_encode(message: &[u8], length: usize) -> Vec<u8> {
+    let hash = pss_hash(&Sha1::digest(message));
+    let db_length = length - hash.len() - 1;
+
+    let mut db = vec![0u8; db_length];
+    db[db_length - 1] = 0x01;
+    for (byte, mask) in db.iter_mut().zip(mgf1_sha512(&hash, db_length)) {
+        *byte ^= mask;
+    }
+    db[0] &= 0x7f;
+
+    let mut encoded = db;
+    encoded.extend_from_slice(&hash);
+    encoded.push(0xbc);
+    encoded
+}
+
+/// Checks a PSS encoded message as created by `pss_encode`
+#[cfg(test)]
+fn pss_verify(message: &[u8], encoded: &[u8]) -> bool {
+    let hash_length = 20;
+    if encoded.len() < hash_length + 2 || encoded[encoded.len() - 1] != 0xbc {
+        return false;
+    }
+    let db_length = encoded.len() - hash_length - 1;
+    let hash = &encoded[db_length..encoded.len() - 1];
+
+    let mut db = encoded[..db_length].to_vec();
+    for (byte, mask) in db.iter_mut().zip(mgf1_sha512(hash, db_length)) {
+        *byte ^= mask;
+    }
+    db[0] &= 0x7f;
+
+    db[..db_length - 1].iter().all(|byte| *byte == 0)
+        && db[db_length - 1] == 0x01
+        && pss_hash(&Sha1::digest(message)) == hash
+}
+
+/// Pads a big-endian number to the size of the modulus
+pub fn to_bytes(number: &BigUint, length: usize) -> Vec<u8> {
+    let bytes = number.to_bytes_be();
+    let mut padded = vec![0u8; length.saturating_sub(bytes.len())];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+/// Signs apps with the private key and certificate of the app developer
+pub struct Signer {
+    key: RsaPrivateKey,
+    certificate: String,
+}
+
+impl Signer {
+    pub fn load(key_path: &Path, certificate_path: &Path) -> Result<Self> {
+        let key = package_signature::private_key(key_path)?;
+        let certificate = fs::read_to_string(certificate_path)
+            .wrap_err_with(|| format!("Failed to read certificate {:?}", certificate_path))?;
+
+        Ok(Signer {
+            key,
+            certificate: certificate.trim().to_owned(),
+        })
+    }
+
+    /// The private key operation is blinded, so its timing doesn't depend
+    /// on the key
+    pub fn sign(&self, hashes: BTreeMap<String, String>) -> Result<Signature> {
+        let length = self.key.size();
+        let encoded =
+            BigUint::from_bytes_be(&pss_encode(signed_message(&hashes).as_bytes(), length));
+        let signature =
+            rsa::hazmat::rsa_decrypt_and_check(&self.key, Some(&mut rand::thread_rng()), &encoded)
+                .map_err(|e| Report::msg(format!("Failed to sign app: {}", e)))?;
+
+        Ok(Signature {
+            hashes,
+            signature: base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                to_bytes(&signature, length),
+            ),
+            certificate: self.certificate.clone(),
+        })
+    }
+}
+
+/// Developer certificates are kept in `~/.nextcloud/certificates` by
+/// default, like for `krankerl sign --package`
+pub fn default_key_paths(app_id: &str) -> Result<(PathBuf, PathBuf)> {
+    let dir = dirs::home_dir()
+        .ok_or_else(|| Report::msg("Could not resolve home dir"))?
+        .join(".nextcloud")
+        .join("certificates");
+    Ok((
+        dir.join(format!("{}.key", app_id)),
+        dir.join(format!("{}.crt", app_id)),
+    ))
+}
+
+/// Hashes the given files of the app and writes the signature into it
+pub fn sign_app(app_path: &Path, files: &[PathBuf], signer: &Signer) -> Result<usize> {
+    let signature = signer.sign(hash_files(app_path, files)?)?;
+    let path = app_path.join(SIGNATURE_PATH);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string_pretty(&signature)?;
+    fs::write(&path, json).wrap_err_with(|| format!("Failed to write signature {:?}", path))?;
+    Ok(signature.hashes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn assets_path() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("assets")
+            .join("code-signing")
+    }
+
+    fn test_signer() -> Signer {
+        let assets = assets_path();
+        Signer::load(
+            &assets.join("recommendations.key"),
+            &assets.join("recommendations.crt"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn encodes_message_like_php() {
+        let mut hashes = BTreeMap::new();
+        hashes.insert("lib/Ä.php".to_owned(), "ab".to_owned());
+        hashes.insert("appinfo/info.xml".to_owned(), "cd".to_owned());
+
+        assert_eq!(
+            r#"{"appinfo\/info.xml":"cd","lib\/\u00c4.php":"ab"}"#,
+            signed_message(&hashes)
+        );
+    }
+
+    #[test]
+    fn hashes_files_except_ignored_ones() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::create_dir(tmp.path().join("appinfo")).unwrap();
+        fs::write(tmp.path().join("appinfo").join("info.xml"), "hello").unwrap();
+        fs::write(tmp.path().join("appinfo").join("signature.json"), "{}").unwrap();
+        fs::write(tmp.path().join(".DS_Store"), "").unwrap();
+        fs::write(tmp.path().join(".webapp-nextcloud-28.0.1-r1"), "").unwrap();
+        let files = vec![
+            PathBuf::from("appinfo/info.xml"),
+            PathBuf::from("appinfo/signature.json"),
+            PathBuf::from(".DS_Store"),
+            PathBuf::from(".webapp-nextcloud-28.0.1-r1"),
+        ];
+
+        let hashes = hash_files(tmp.path(), &files).unwrap();
+
+        assert_eq!(1, hashes.len());
+        assert!(hashes["appinfo/info.xml"].starts_with("9b71d224bd62f378"));
+    }
+
+    #[test]
+    fn matches_webapp_config_files() {
+        assert!(is_webapp_config_file(".webapp-nextcloud-28.0.1"));
+        assert!(is_webapp_config_file(".webapp-nextcloud-28.0.1-r12"));
+        assert!(!is_webapp_config_file(".webapp-nextcloud-28.0"));
+        assert!(!is_webapp_config_file(".webapp-nextcloud-28.0.1-rc1"));
+        assert!(!is_webapp_config_file(".webapp-nextcloud-notes"));
+        assert!(!is_webapp_config_file("webapp-nextcloud-28.0.1"));
+    }
+
+    /// `app.signature.json` was created independently of Krankerl, with
+    /// `sha512sum` for the hashes of the files in `app` and
+    /// `openssl pkeyutl -sign` with `rsa_padding_mode:pss`, `digest:sha1`,
+    /// `rsa_mgf1_md:sha512` and `rsa_pss_saltlen:0` for the signature.
+    /// Without salt, PSS signatures are deterministic.
+    #[test]
+    fn signs_like_openssl() {
+        let expected: Signature = serde_json::from_str(
+            &fs::read_to_string(assets_path().join("app.signature.json")).unwrap(),
+        )
+        .unwrap();
+        let files = vec![
+            PathBuf::from("appinfo/info.xml"),
+            PathBuf::from("js/main.js"),
+            PathBuf::from("lib/Ä.php"),
+        ];
+
+        let hashes = hash_files(&assets_path().join("app"), &files).unwrap();
+        let signature = test_signer().sign(hashes).unwrap();
+
+        assert_eq!(expected.hashes, signature.hashes);
+        assert_eq!(expected.signature, signature.signature);
+        assert_eq!(expected.certificate, signature.certificate);
+    }
+
+    #[test]
+    fn signs_hashes_with_pss() {
+        let signer = test_signer();
+        let mut hashes = BTreeMap::new();
+        hashes.insert("appinfo/info.xml".to_owned(), "ab".to_owned());
+
+        let signature = signer.sign(hashes).unwrap();
+
+        assert!(signature
+            .certificate
+            .starts_with("-----BEGIN CERTIFICATE-----"));
+        let bytes = base64::Engine::decode(
+            &base64::engine::general_purpose::STANDARD,
+            &signature.signature,
+        )
+        .unwrap();
+        assert_eq!(256, bytes.len());
+        let encoded =
+            rsa::hazmat::rsa_encrypt(&signer.key.to_public_key(), &BigUint::from_bytes_be(&bytes))
+                .unwrap();
+        let message = signed_message(&signature.hashes);
+        assert!(pss_verify(message.as_bytes(), &to_bytes(&encoded, 256)));
+        assert!(!pss_verify(b"tampered", &to_bytes(&encoded, 256)));
+    }
+
+    #[test]
+    fn writes_signature_into_app() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::create_dir(tmp.path().join("lib")).unwrap();
+        fs::write(tmp.path().join("lib").join("App.php"), "<?php").unwrap();
+
+        let count = sign_app(tmp.path(), &[PathBuf::from("lib/App.php")], &test_signer()).unwrap();
+
+        assert_eq!(1, count);
+        let signature: Signature = serde_json::from_str(
+            &fs::read_to_string(tmp.path().join("appinfo").join("signature.json")).unwrap(),
+        )
+        .unwrap();
+        assert!(signature.hashes.contains_key("lib/App.php"));
+    }
+}
@@ -1,11 +1,19 @@
 use std::convert::From;
-use std::path::Path;
-use std::process::Command;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
 use color_eyre::{Report, Result};
 
-use crate::config::app::PackageConfig;
+use crate::config::app::{PackageCommand, PackageConfig, PackageStep};
 use color_eyre::eyre::WrapErr;
 
 pub trait PackageCommands {
@@ -14,39 +22,372 @@ pub trait PackageCommands {
 
 #[derive(Debug)]
 pub struct CommandList {
-    cmds: Vec<String>,
+    cmds: Vec<PackageStep>,
+    env: Vec<(String, String)>,
+    vars: Vec<(String, String)>,
+    log: Option<PathBuf>,
+}
+
+impl CommandList {
+    pub fn new(cmds: Vec<PackageStep>) -> Self {
+        CommandList {
+            cmds,
+            env: vec![],
+            vars: vec![],
+            log: None,
+        }
+    }
+
+    /// Sets a variable that replaces the `{name}` placeholder in all commands
+    /// and is exported to them as `KRANKERL_<NAME>`
+    pub fn var<V: Into<String>>(mut self, name: &str, value: V) -> Self {
+        let value = value.into();
+        self.env
+            .push((format!("KRANKERL_{}", name.to_uppercase()), value.clone()));
+        self.vars.push((format!("{{{}}}", name), value));
+        self
+    }
+
+    /// Appends the output of all commands to the given log file
+    pub fn log_to(mut self, log: &Path) -> Self {
+        self.log = Some(log.to_path_buf());
+        self
+    }
+
+    /// Replaces the known placeholders, anything else in braces is left to
+    /// the shell
+    fn interpolate(&self, value: &str, quote: fn(&str) -> String) -> String {
+        self.vars
+            .iter()
+            .fold(value.to_owned(), |value, (placeholder, replacement)| {
+                value.replace(placeholder, &quote(replacement))
+            })
+    }
+}
+
+/// Quotes the value as a single word for `sh`, so paths with spaces or
+/// shell syntax are passed on unchanged
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Path of the log the output of all packaging commands is written to
+pub fn log_path(app_path: &Path) -> PathBuf {
+    app_path.join("build").join("krankerl.log")
+}
+
+/// Creates an empty packaging log, replacing the one of the previous run
+pub fn start_log(app_path: &Path) -> Result<PathBuf> {
+    let path = log_path(app_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(&path).wrap_err_with(|| format!("Failed to create log file {:?}", path))?;
+    Ok(path)
+}
+
+pub fn run(cmd: &str, cwd: &Path, log: &Path) -> Result<()> {
+    run_group(&[PackageCommand::new(cmd)], cwd, &[], &open_log(Some(log))?)
+}
+
+type Log = Option<Arc<Mutex<File>>>;
+
+fn open_log(path: Option<&Path>) -> Result<Log> {
+    match path {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .wrap_err_with(|| format!("Failed to open log file {:?}", path))?;
+            Ok(Some(Arc::new(Mutex::new(file))))
+        }
+        None => Ok(None),
+    }
+}
+
+fn write_log(log: &Log, line: &str) {
+    if let Some(log) = log {
+        // The log is only a copy of the output, don't fail the build for it
+        let _ = writeln!(log.lock().unwrap(), "{}", line);
+    }
+}
+
+/// Prints the lines of the command's output as soon as they are written,
+/// prefixed with the command's name, and copies them to the log. Reports
+/// to `done` once the output is closed.
+fn stream<R: Read + Send + 'static>(
+    reader: Option<R>,
+    prefix: String,
+    log: Log,
+    print: fn(&str),
+    done: Sender<()>,
+) {
+    thread::spawn(move || {
+        if let Some(reader) = reader {
+            for line in BufReader::new(reader).split(b'\n') {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                let line = format!("[{}] {}", prefix, String::from_utf8_lossy(&line));
+                print(&line);
+                write_log(&log, &line);
+            }
+        }
+        let _ = done.send(());
+    });
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long the output is read after a command exited. Background processes
+/// it started may keep the output open much longer.
+const OUTPUT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// Starts the command in its own process group
+#[cfg(unix)]
+fn new_process_group(command: &mut Command) {
+    command.process_group(0);
+}
+
+/// Process groups are only used on Unix
+#[cfg(not(unix))]
+fn new_process_group(_command: &mut Command) {}
+
+/// Kills the whole process group of the child, the shell's children could
+/// otherwise keep running
+#[cfg(unix)]
+fn kill_process_group(child: &mut Child) {
+    unsafe {
+        libc::kill(-(child.id() as i32), libc::SIGKILL);
+    }
+}
+
+/// Without process groups only the shell itself can be killed
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut Child) {
+    let _ = child.kill();
+}
+
+/// A started command whose output is being streamed
+struct Running<'a> {
+    cmd: &'a PackageCommand,
+    child: Child,
+    /// Whether the command runs in its own process group
+    group: bool,
+    deadline: Option<Instant>,
+    /// Receives one message per output stream once it is closed
+    output: Receiver<()>,
+}
+
+impl<'a> Running<'a> {
+    /// Commands that may have to be killed run in their own process group,
+    /// so their children are killed as well. All others stay in the
+    /// terminal's group and receive its Ctrl-C.
+    fn spawn(
+        cmd: &'a PackageCommand,
+        cwd: &Path,
+        env: &[(String, String)],
+        log: &Log,
+        group: bool,
+    ) -> Result<Self> {
+        let cwd = match cmd.cwd() {
+            Some(dir) => cwd.join(dir),
+            None => cwd.to_path_buf(),
+        };
+        println!("Running `{}`...", cmd.run());
+        write_log(log, &format!("$ {} (in {:?})", cmd.run(), cwd));
+
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(cmd.run())
+            .current_dir(&cwd)
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .envs(cmd.env())
+            // Commands can't prompt, a background process group would
+            // be stopped when it reads from the terminal
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if group {
+            new_process_group(&mut command);
+        }
+        let mut child = command
+            .spawn()
+            .wrap_err_with(|| format!("Cannot start command <{}>: ", cmd.run()))?;
+        let (done, output) = mpsc::channel();
+        stream(
+            child.stdout.take(),
+            cmd.name().clone(),
+            log.clone(),
+            |line| println!("{}", line),
+            done.clone(),
+        );
+        stream(
+            child.stderr.take(),
+            cmd.name().clone(),
+            log.clone(),
+            |line| eprintln!("{}", line),
+            done,
+        );
+
+        Ok(Running {
+            cmd,
+            child,
+            group,
+            deadline: cmd.timeout().map(|timeout| Instant::now() + timeout),
+            output,
+        })
+    }
+
+    /// Returns the result of the command once it exited or timed out. With
+    /// `block`, commands without a timeout are waited for.
+    fn poll(&mut self, block: bool) -> Option<Result<()>> {
+        let status = if block && self.deadline.is_none() {
+            self.child.wait().map(Some)
+        } else {
+            self.child.try_wait()
+        };
+        let status = match status {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                let timed_out = self
+                    .deadline
+                    .map(|deadline| Instant::now() >= deadline)
+                    .unwrap_or(false);
+                if !timed_out {
+                    return None;
+                }
+                self.kill();
+                return Some(Err(Report::msg(format!(
+                    "Command <{}> timed out after {} seconds",
+                    self.cmd.run(),
+                    self.cmd.timeout().unwrap_or_default().as_secs()
+                ))));
+            }
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        Some(match status.code() {
+            Some(0) => Ok(()),
+            Some(code) => Err(Report::msg(format!(
+                "Command <{}> returned exit status {:?}",
+                self.cmd.run(),
+                code
+            ))),
+            None => Err(Report::msg(format!(
+                "Command <{}> was aborted by a signal",
+                self.cmd.run()
+            ))),
+        })
+    }
+
+    fn kill(&mut self) {
+        if self.group {
+            kill_process_group(&mut self.child);
+        } else {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+
+    /// Waits until all output of the command has been printed, but not for
+    /// background processes that keep it open
+    fn finish(self) {
+        let deadline = Instant::now() + OUTPUT_GRACE_PERIOD;
+        for _ in 0..2 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self.output.recv_timeout(remaining).is_err() {
+                println!(
+                    "Output of `{}` is still open, not waiting for its background processes",
+                    self.cmd.run()
+                );
+                return;
+            }
+        }
+    }
+}
+
+fn kill_all(running: Vec<Running>) {
+    for mut cmd in running {
+        println!("Killing `{}`", cmd.cmd.run());
+        cmd.kill();
+        cmd.finish();
+    }
+}
+
+/// Runs the commands concurrently. As soon as one of them fails, all others
+/// are killed unless the failed command is allowed to fail.
+fn run_group(
+    cmds: &[PackageCommand],
+    cwd: &Path,
+    env: &[(String, String)],
+    log: &Log,
+) -> Result<()> {
+    let mut running = Vec::with_capacity(cmds.len());
+    for cmd in cmds {
+        let group = cmds.len() > 1 || cmd.timeout().is_some();
+        match Running::spawn(cmd, cwd, env, log, group) {
+            Ok(started) => running.push(started),
+            Err(e) => {
+                kill_all(running);
+                return Err(e);
+            }
+        }
+    }
+
+    while !running.is_empty() {
+        // A single command doesn't have to be polled
+        let block = running.len() == 1;
+        let mut i = 0;
+        while i < running.len() {
+            let result = match running[i].poll(block) {
+                Some(result) => result,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let finished = running.remove(i);
+            let cmd = finished.cmd;
+            finished.finish();
+
+            if let Err(e) = result {
+                write_log(log, &e.to_string());
+                if !cmd.allow_failure() {
+                    kill_all(running);
+                    return Err(e);
+                }
+                println!("Ignoring failed command `{}`: {}", cmd.run(), e);
+            }
+        }
+        if !running.is_empty() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    Ok(())
 }
 
 impl PackageCommands for CommandList {
     fn execute(&self, cwd: &Path) -> Result<()> {
         println!("Executing packaging commands...");
-        for cmd in &self.cmds {
-            println!("Running `{}`...", cmd);
-            Command::new("sh")
-                .arg("-c")
-                .arg(cmd)
-                .current_dir(cwd)
-                .output()
-                .wrap_err_with(|| format!("Cannot start command <{}>: ", cmd))
-                .and_then(|output| {
-                    if output.status.success() {
-                        Ok(())
-                    } else {
-                        match output.status.code() {
-                            Some(code) => Err(Report::msg(format!(
-                                "Command <{}> returned exit status {:?}\n\nstdout: {}\nstderr: {}",
-                                cmd,
-                                code,
-                                String::from_utf8_lossy(&output.stdout),
-                                String::from_utf8_lossy(&output.stderr)
-                            ))),
-                            None => Err(Report::msg(format!(
-                                "Command <{}> was aborted by a signal",
-                                cmd
-                            ))),
-                        }
-                    }
-                })?;
+        let log = open_log(self.log.as_deref())?;
+        for step in &self.cmds {
+            let cmds: Vec<PackageCommand> = step
+                .commands()
+                .iter()
+                .map(|cmd| {
+                    cmd.map_values(
+                        |run| self.interpolate(run, shell_quote),
+                        |value| self.interpolate(value, str::to_owned),
+                    )
+                })
+                .collect();
+            if let PackageStep::Parallel(_) = step {
+                println!("Running {} commands in parallel...", cmds.len());
+            }
+            run_group(&cmds, cwd, &self.env, &log)?;
         }
         println!("Executed all packaging commands.");
         Ok(())
@@ -55,16 +396,223 @@ impl PackageCommands for CommandList {
 
 impl<'a> From<&'a PackageConfig> for CommandList {
     fn from(config: &'a PackageConfig) -> Self {
-        CommandList {
-            cmds: config.before_cmds().clone(),
-        }
+        CommandList::new(config.before_cmds().clone())
     }
 }
 
 impl From<PackageConfig> for CommandList {
     fn from(config: PackageConfig) -> Self {
-        CommandList {
-            cmds: config.before_cmds().clone(),
-        }
+        CommandList::new(config.before_cmds().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::config::app::get_config;
+
+    fn commands_from(dir: &Path, toml: &str) -> CommandList {
+        fs::write(dir.join("krankerl.toml"), toml).unwrap();
+        let config = get_config(dir, None).unwrap().unwrap();
+        CommandList::from(config.package())
+    }
+
+    #[test]
+    fn runs_command_with_cwd_and_env() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::create_dir(tmp.path().join("js")).unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    { run = "echo $GREETING > out", cwd = "js", env = { GREETING = "hello" } },
+]"#,
+        );
+
+        cmds.execute(tmp.path()).unwrap();
+
+        let out = fs::read_to_string(tmp.path().join("js").join("out")).unwrap();
+        assert_eq!("hello\n", out);
+    }
+
+    #[test]
+    fn writes_output_to_log() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let log = start_log(tmp.path()).unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    "echo out",
+    { run = "echo err >&2", name = "errors" },
+]"#,
+        )
+        .log_to(&log);
+
+        cmds.execute(tmp.path()).unwrap();
+
+        let log = fs::read_to_string(log).unwrap();
+        assert!(log.contains("[echo out] out\n"));
+        assert!(log.contains("[errors] err\n"));
+    }
+
+    #[test]
+    fn replaces_placeholders() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::create_dir(tmp.path().join("news")).unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    { run = "echo {app_id} $KRANKERL_VERSION {unknown} > {app_id}.txt", cwd = "{app_id}" },
+]"#,
+        )
+        .var("app_id", "news")
+        .var("version", "1.2.3");
+
+        cmds.execute(tmp.path()).unwrap();
+
+        let out = fs::read_to_string(tmp.path().join("news").join("news.txt")).unwrap();
+        assert_eq!("news 1.2.3 {unknown}\n", out);
+    }
+
+    #[test]
+    fn quotes_placeholders_in_commands() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let source_dir = "/tmp/my app's; touch pwned $(touch pwned)";
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    { run = "printf %s {source_dir} > out; printf %s \"$SOURCE\" > env", env = { SOURCE = "{source_dir}" } },
+]"#,
+        )
+        .var("source_dir", source_dir);
+
+        cmds.execute(tmp.path()).unwrap();
+
+        assert_eq!(
+            source_dir,
+            fs::read_to_string(tmp.path().join("out")).unwrap()
+        );
+        assert_eq!(
+            source_dir,
+            fs::read_to_string(tmp.path().join("env")).unwrap()
+        );
+        assert!(!tmp.path().join("pwned").exists());
+    }
+
+    #[test]
+    fn runs_parallel_commands_concurrently() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    { parallel = [
+        { run = "while [ ! -f second ]; do sleep 0.1; done; touch first", timeout = 10 },
+        "touch second",
+    ] },
+]"#,
+        );
+
+        cmds.execute(tmp.path()).unwrap();
+
+        assert!(tmp.path().join("first").exists());
+    }
+
+    #[test]
+    fn kills_parallel_commands_on_failure() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    { parallel = ["sleep 30", "false"] },
+    "touch done",
+]"#,
+        );
+
+        let started = Instant::now();
+        let result = cmds.execute(tmp.path());
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!tmp.path().join("done").exists());
+    }
+
+    #[test]
+    fn ignores_allowed_failure() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    { run = "false", allow_failure = true },
+    "touch done",
+]"#,
+        );
+
+        cmds.execute(tmp.path()).unwrap();
+
+        assert!(tmp.path().join("done").exists());
+    }
+
+    #[test]
+    fn runs_commands_without_stdin() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    "read line; echo $? > out",
+]"#,
+        );
+
+        cmds.execute(tmp.path()).unwrap();
+
+        let out = fs::read_to_string(tmp.path().join("out")).unwrap();
+        assert_eq!("1\n", out);
+    }
+
+    #[test]
+    fn does_not_wait_for_background_processes() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    "sleep 30 &",
+    "touch done",
+]"#,
+        );
+
+        let started = Instant::now();
+        cmds.execute(tmp.path()).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(tmp.path().join("done").exists());
+    }
+
+    #[test]
+    fn kills_command_after_timeout() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let cmds = commands_from(
+            tmp.path(),
+            r#"[package]
+before_cmds = [
+    { run = "sleep 30", timeout = 1 },
+]"#,
+        );
+
+        let started = Instant::now();
+        let result = cmds.execute(tmp.path());
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(10));
     }
 }
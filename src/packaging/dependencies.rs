@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use composer::Composer;
+use npm_scripts::NpmScripts;
+
+use crate::config::app::DependencyConfig;
+use crate::packaging::commands;
+
+fn find_npm_path(app_path: &Path) -> Option<PathBuf> {
+    let in_root = app_path.to_path_buf();
+    let in_js = app_path.join("js");
+
+    if NpmScripts::new(&in_root).is_available() {
+        Some(in_root)
+    } else if NpmScripts::new(&in_js).is_available() {
+        Some(in_js)
+    } else {
+        None
+    }
+}
+
+fn install_composer(config: &DependencyConfig, app_path: &Path, log: &Path) -> Result<()> {
+    if Composer::new(app_path).is_available() {
+        println!("Installing composer packages...");
+        commands::run(config.composer_cmd(), app_path, log)
+            .wrap_err("Failed to install composer packages")?;
+        println!("Installed composer packages.");
+    } else {
+        println!("No composer config found.");
+    }
+    Ok(())
+}
+
+fn install_npm(config: &DependencyConfig, app_path: &Path, log: &Path) -> Result<()> {
+    match find_npm_path(app_path) {
+        Some(npm_path) => {
+            println!("Installing npm packages...");
+            commands::run(config.npm_cmd(), &npm_path, log)
+                .wrap_err("Failed to install npm packages")?;
+            println!("Installed npm packages.");
+        }
+        None => println!("No npm config found."),
+    }
+    Ok(())
+}
+
+pub fn install(config: &DependencyConfig, app_path: &Path, log: &Path) -> Result<()> {
+    install_composer(config, app_path, log)?;
+    install_npm(config, app_path, log)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn finds_npm_config_in_root() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::write(tmp.path().join("package.json"), "{}").unwrap();
+
+        assert_eq!(Some(tmp.path().to_path_buf()), find_npm_path(tmp.path()));
+    }
+
+    #[test]
+    fn finds_npm_config_in_js_dir() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::create_dir(tmp.path().join("js")).unwrap();
+        fs::write(tmp.path().join("js").join("package.json"), "{}").unwrap();
+
+        assert_eq!(Some(tmp.path().join("js")), find_npm_path(tmp.path()));
+    }
+
+    #[test]
+    fn finds_no_npm_config() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+
+        assert_eq!(None, find_npm_path(tmp.path()));
+    }
+}
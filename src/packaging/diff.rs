@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Report, Result};
+use flate2::read::GzDecoder;
+use tar::Archive;
+use xpath_reader::{Context, Reader};
+
+use crate::packaging::manifest::HashingReader;
+
+/// Fields of `info.xml` that are compared, with the XPath to read them
+const INFO_FIELDS: [(&str, &str); 7] = [
+    ("version", "//info/version"),
+    ("php min-version", "//info/dependencies/php/@min-version"),
+    ("php max-version", "//info/dependencies/php/@max-version"),
+    (
+        "nextcloud min-version",
+        "//info/dependencies/nextcloud/@min-version",
+    ),
+    (
+        "nextcloud max-version",
+        "//info/dependencies/nextcloud/@max-version",
+    ),
+    ("lib dependencies", "//info/dependencies/lib"),
+    ("database dependencies", "//info/dependencies/database"),
+];
+
+struct ArchiveFile {
+    size: u64,
+    sha256: String,
+}
+
+/// Files of an archive relative to its top-level directory
+struct ArchiveContents {
+    files: BTreeMap<PathBuf, ArchiveFile>,
+    info_xml: Option<String>,
+}
+
+fn read_archive(archive_path: &Path) -> Result<ArchiveContents> {
+    let file = File::open(archive_path)
+        .wrap_err_with(|| format!("Failed to open archive {:?}", archive_path))?;
+    let mut archive = Archive::new(GzDecoder::new(file));
+    let mut files = BTreeMap::new();
+    let mut info_xml = None;
+
+    for entry in archive
+        .entries()
+        .wrap_err_with(|| format!("Failed to read archive {:?}", archive_path))?
+    {
+        let entry = entry?;
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+        // Archives created with `tar -C <dir> .` prefix all paths with `./`
+        let path = entry.path()?.into_owned();
+        let components: Vec<Component> = path
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        let skip = if components.len() > 1 { 1 } else { 0 };
+        let path: PathBuf = components[skip..].iter().collect();
+
+        let mut reader = HashingReader::new(entry);
+        if path == Path::new("appinfo/info.xml") {
+            let mut xml = String::new();
+            reader.read_to_string(&mut xml)?;
+            info_xml = Some(xml);
+        } else {
+            io::copy(&mut reader, &mut io::sink())?;
+        }
+        let (size, sha256) = reader.finish();
+        files.insert(path, ArchiveFile { size, sha256 });
+    }
+
+    Ok(ArchiveContents { files, info_xml })
+}
+
+fn read_info_fields(xml: &str) -> Result<Vec<Option<String>>> {
+    let context = Context::new();
+    let reader = Reader::from_str(xml, Some(&context))
+        .map_err(|e| Report::msg(format!("Failed to parse info.xml: {}", e)))?;
+
+    INFO_FIELDS
+        .iter()
+        .map(|(name, xpath)| {
+            let values: Vec<String> = reader.read(*xpath).map_err(|e| {
+                Report::msg(format!("Failed to read {} from info.xml: {}", name, e))
+            })?;
+            Ok(if values.is_empty() {
+                None
+            } else {
+                Some(values.join(", "))
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FileChange {
+    Added { size: u64 },
+    Removed { size: u64 },
+    Changed { old_size: u64, new_size: u64 },
+}
+
+impl FileChange {
+    /// Change of the archive's content size caused by this file
+    pub fn size_delta(&self) -> i64 {
+        match self {
+            FileChange::Added { size } => *size as i64,
+            FileChange::Removed { size } => -(*size as i64),
+            FileChange::Changed { old_size, new_size } => *new_size as i64 - *old_size as i64,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InfoChange {
+    pub field: &'static str,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+pub struct PackageDiff {
+    pub files: Vec<(PathBuf, FileChange)>,
+    pub info: Vec<InfoChange>,
+}
+
+pub fn diff_archives(old_path: &Path, new_path: &Path) -> Result<PackageDiff> {
+    let old = read_archive(old_path)?;
+    let new = read_archive(new_path)?;
+
+    let paths: BTreeSet<&PathBuf> = old.files.keys().chain(new.files.keys()).collect();
+    let files = paths
+        .into_iter()
+        .filter_map(|path| {
+            let change = match (old.files.get(path), new.files.get(path)) {
+                (None, Some(file)) => FileChange::Added { size: file.size },
+                (Some(file), None) => FileChange::Removed { size: file.size },
+                (Some(old), Some(new)) if old.sha256 != new.sha256 => FileChange::Changed {
+                    old_size: old.size,
+                    new_size: new.size,
+                },
+                _ => return None,
+            };
+            Some((path.clone(), change))
+        })
+        .collect();
+
+    let old_info = match &old.info_xml {
+        Some(xml) => read_info_fields(xml)?,
+        None => vec![None; INFO_FIELDS.len()],
+    };
+    let new_info = match &new.info_xml {
+        Some(xml) => read_info_fields(xml)?,
+        None => vec![None; INFO_FIELDS.len()],
+    };
+    let info = INFO_FIELDS
+        .iter()
+        .zip(old_info.into_iter().zip(new_info))
+        .filter(|(_, (old, new))| old != new)
+        .map(|((field, _), (old, new))| InfoChange { field, old, new })
+        .collect();
+
+    Ok(PackageDiff { files, info })
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tar::{Builder, Header};
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn info_xml(version: &str, nextcloud_max: u32) -> String {
+        format!(
+            "<?xml version=\"1.0\"?>
+            <info>
+                <id>news</id>
+                <name>News</name>
+                <version>{}</version>
+                <dependencies>
+                    <php min-version=\"8.0\"/>
+                    <nextcloud min-version=\"26\" max-version=\"{}\"/>
+                </dependencies>
+            </info>",
+            version, nextcloud_max
+        )
+    }
+
+    fn build_archive(path: &Path, files: &[(&str, &str)]) {
+        let mut builder = Builder::new(GzEncoder::new(
+            File::create(path).unwrap(),
+            Compression::default(),
+        ));
+        for (name, content) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            // Written as is, `append_data` would normalize the path
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            builder.append(&header, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn diffs_files() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let old = tmp.path().join("old.tar.gz");
+        let new = tmp.path().join("new.tar.gz");
+        build_archive(
+            &old,
+            &[
+                ("news/lib/Same.php", "same"),
+                ("news/lib/Changed.php", "old"),
+                ("news/lib/Removed.php", "removed"),
+            ],
+        );
+        build_archive(
+            &new,
+            &[
+                ("news/lib/Same.php", "same"),
+                ("news/lib/Changed.php", "changed"),
+                ("news/lib/Added.php", "a"),
+            ],
+        );
+
+        let diff = diff_archives(&old, &new).unwrap();
+
+        assert_eq!(
+            vec![
+                (
+                    PathBuf::from("lib/Added.php"),
+                    FileChange::Added { size: 1 }
+                ),
+                (
+                    PathBuf::from("lib/Changed.php"),
+                    FileChange::Changed {
+                        old_size: 3,
+                        new_size: 7
+                    }
+                ),
+                (
+                    PathBuf::from("lib/Removed.php"),
+                    FileChange::Removed { size: 7 }
+                ),
+            ],
+            diff.files
+        );
+        assert_eq!(4, diff.files[1].1.size_delta());
+        assert!(diff.info.is_empty());
+    }
+
+    #[test]
+    fn ignores_current_dir_prefix() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let old = tmp.path().join("old.tar.gz");
+        let new = tmp.path().join("new.tar.gz");
+        build_archive(&old, &[("news/lib/Same.php", "same")]);
+        build_archive(&new, &[("./news/lib/Same.php", "same")]);
+
+        let diff = diff_archives(&old, &new).unwrap();
+
+        assert!(diff.files.is_empty());
+    }
+
+    #[test]
+    fn diffs_info_xml() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let old = tmp.path().join("old.tar.gz");
+        let new = tmp.path().join("new.tar.gz");
+        build_archive(&old, &[("news/appinfo/info.xml", &info_xml("1.0.0", 27))]);
+        build_archive(&new, &[("news/appinfo/info.xml", &info_xml("1.1.0", 28))]);
+
+        let diff = diff_archives(&old, &new).unwrap();
+
+        assert_eq!(
+            vec![
+                InfoChange {
+                    field: "version",
+                    old: Some("1.0.0".to_owned()),
+                    new: Some("1.1.0".to_owned()),
+                },
+                InfoChange {
+                    field: "nextcloud max-version",
+                    old: Some("27".to_owned()),
+                    new: Some("28".to_owned()),
+                },
+            ],
+            diff.info
+        );
+    }
+}
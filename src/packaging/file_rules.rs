@@ -0,0 +1,107 @@
+use std::path::Path;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// The `include` and `exclude` globs of the package config. They use the
+/// gitignore syntax and are relative to the app's root directory.
+pub struct FileRules {
+    include: Option<Gitignore>,
+    exclude: Gitignore,
+}
+
+fn build_globs(root: &Path, globs: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for glob in globs {
+        builder
+            .add_line(None, glob)
+            .wrap_err_with(|| format!("Invalid glob <{}>", glob))?;
+    }
+    Ok(builder.build()?)
+}
+
+impl FileRules {
+    pub fn new(root: &Path, include: &[String], exclude: &[String]) -> Result<Self> {
+        // Without include globs everything is packaged that is not excluded
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_globs(root, include)?)
+        };
+
+        Ok(FileRules {
+            include,
+            exclude: build_globs(root, exclude)?,
+        })
+    }
+
+    /// Checks a path relative to the app's root directory. Directories are
+    /// only rejected if they are excluded, they might still contain files
+    /// that are included.
+    pub fn is_packaged(&self, path: &Path, is_dir: bool) -> bool {
+        if self
+            .exclude
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+        {
+            return false;
+        }
+        match &self.include {
+            Some(_) if is_dir => true,
+            Some(include) => include
+                .matched_path_or_any_parents(path, is_dir)
+                .is_ignore(),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(include: &[&str], exclude: &[&str]) -> FileRules {
+        let include: Vec<String> = include.iter().map(|glob| glob.to_string()).collect();
+        let exclude: Vec<String> = exclude.iter().map(|glob| glob.to_string()).collect();
+        FileRules::new(Path::new("/app"), &include, &exclude).unwrap()
+    }
+
+    #[test]
+    fn packages_everything_by_default() {
+        let rules = rules(&[], &[]);
+
+        assert!(rules.is_packaged(Path::new("lib/Controller.php"), false));
+        assert!(rules.is_packaged(Path::new("tests"), true));
+    }
+
+    #[test]
+    fn excludes_files_and_directories() {
+        let rules = rules(&[], &["tests/", "*.map"]);
+
+        assert!(!rules.is_packaged(Path::new("tests"), true));
+        assert!(!rules.is_packaged(Path::new("tests/unit/Test.php"), false));
+        assert!(!rules.is_packaged(Path::new("js/app.js.map"), false));
+        assert!(rules.is_packaged(Path::new("js/app.js"), false));
+    }
+
+    #[test]
+    fn only_packages_included_files() {
+        let rules = rules(&["/appinfo/", "/lib/", "/js/*.js"], &["lib/Vendor/"]);
+
+        assert!(rules.is_packaged(Path::new("appinfo/info.xml"), false));
+        assert!(rules.is_packaged(Path::new("lib/Controller.php"), false));
+        assert!(rules.is_packaged(Path::new("js"), true));
+        assert!(rules.is_packaged(Path::new("js/app.js"), false));
+        assert!(!rules.is_packaged(Path::new("js/app.js.map"), false));
+        assert!(!rules.is_packaged(Path::new("lib/Vendor/Lib.php"), false));
+        assert!(!rules.is_packaged(Path::new("README.md"), false));
+    }
+
+    #[test]
+    fn rejects_invalid_globs() {
+        let include = vec!["lib/[".to_owned()];
+
+        assert!(FileRules::new(Path::new("/app"), &include, &[]).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use color_eyre::{Report, Result};
+
+use crate::packaging::lock_files::Package;
+
+fn tokenize(expression: &str) -> Vec<String> {
+    expression
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Evaluates SPDX license expressions. `OR` binds weaker than `AND`, the
+/// exception of `WITH` only adds permissions and is ignored.
+struct Evaluator<'a> {
+    tokens: Vec<String>,
+    position: usize,
+    allowed: &'a [String],
+}
+
+impl<'a> Evaluator<'a> {
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_is(&self, keyword: &str) -> bool {
+        self.tokens
+            .get(self.position)
+            .map(|token| token.eq_ignore_ascii_case(keyword))
+            .unwrap_or(false)
+    }
+
+    fn or_expression(&mut self) -> Option<bool> {
+        let mut allowed = self.and_expression()?;
+        while self.peek_is("OR") {
+            self.position += 1;
+            allowed |= self.and_expression()?;
+        }
+        Some(allowed)
+    }
+
+    fn and_expression(&mut self) -> Option<bool> {
+        let mut allowed = self.license()?;
+        while self.peek_is("AND") {
+            self.position += 1;
+            allowed &= self.license()?;
+        }
+        Some(allowed)
+    }
+
+    fn license(&mut self) -> Option<bool> {
+        let token = self.next()?;
+        if token == "(" {
+            let allowed = self.or_expression()?;
+            return if self.next()? == ")" {
+                Some(allowed)
+            } else {
+                None
+            };
+        }
+        if self.peek_is("WITH") {
+            self.position += 2;
+        }
+        Some(
+            self.allowed
+                .iter()
+                .any(|license| license.eq_ignore_ascii_case(&token)),
+        )
+    }
+}
+
+/// Whether the SPDX expression is satisfied by the allowed licenses.
+/// Invalid expressions are never allowed.
+pub fn is_allowed(expression: &str, allowed: &[String]) -> bool {
+    let mut evaluator = Evaluator {
+        tokens: tokenize(expression),
+        position: 0,
+        allowed,
+    };
+    match evaluator.or_expression() {
+        Some(result) => result && evaluator.position == evaluator.tokens.len(),
+        None => false,
+    }
+}
+
+/// Packages may declare several licenses to choose from. Packages without
+/// a license are never allowed.
+pub fn is_package_allowed(package: &Package, allowed: &[String]) -> bool {
+    package
+        .licenses
+        .iter()
+        .any(|license| is_allowed(license, allowed))
+}
+
+pub fn license_text(package: &Package) -> String {
+    if package.licenses.is_empty() {
+        "(none)".to_owned()
+    } else {
+        package.licenses.join(" OR ")
+    }
+}
+
+/// Fails if any package uses a license that is not allowed
+pub fn check(packages: &[Package], allowed: &[String]) -> Result<()> {
+    if allowed.is_empty() {
+        return Err(Report::msg(
+            "No allowed licenses are configured in [package.licenses]",
+        ));
+    }
+
+    let violations: Vec<String> = packages
+        .iter()
+        .filter(|package| !is_package_allowed(package, allowed))
+        .map(|package| {
+            format!(
+                "{} {} ({}): {}",
+                package.name,
+                package.version,
+                package.ecosystem.name(),
+                license_text(package)
+            )
+        })
+        .collect();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Report::msg(format!(
+            "Licenses not allowed:\n{}",
+            violations
+                .iter()
+                .map(|violation| format!("  - {}", violation))
+                .collect::<Vec<String>>()
+                .join("\n")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packaging::lock_files::Ecosystem;
+
+    fn allowed() -> Vec<String> {
+        vec!["MIT".to_owned(), "AGPL-3.0-or-later".to_owned()]
+    }
+
+    fn package(licenses: &[&str]) -> Package {
+        Package {
+            ecosystem: Ecosystem::Npm,
+            name: "pkg".to_owned(),
+            version: "1.0.0".to_owned(),
+            licenses: licenses.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn evaluates_license_expressions() {
+        assert!(is_allowed("MIT", &allowed()));
+        assert!(is_allowed("mit", &allowed()));
+        assert!(!is_allowed("GPL-2.0-only", &allowed()));
+        assert!(is_allowed("(MIT OR Apache-2.0)", &allowed()));
+        assert!(!is_allowed("MIT AND Apache-2.0", &allowed()));
+        assert!(is_allowed(
+            "Apache-2.0 AND MIT OR AGPL-3.0-or-later",
+            &allowed()
+        ));
+        assert!(is_allowed(
+            "AGPL-3.0-or-later WITH Classpath-exception-2.0",
+            &allowed()
+        ));
+        assert!(!is_allowed("(MIT", &allowed()));
+        assert!(!is_allowed("MIT Apache-2.0", &allowed()));
+        assert!(!is_allowed("", &allowed()));
+    }
+
+    #[test]
+    fn allows_any_declared_license() {
+        assert!(is_package_allowed(
+            &package(&["GPL-2.0-only", "MIT"]),
+            &allowed()
+        ));
+        assert!(!is_package_allowed(&package(&[]), &allowed()));
+    }
+
+    #[test]
+    fn reports_packages_with_licenses_not_allowed() {
+        let packages = vec![package(&["MIT"]), package(&["BSD-4-Clause"]), package(&[])];
+
+        let error = check(&packages, &allowed()).unwrap_err().to_string();
+
+        assert!(error.contains("pkg 1.0.0 (npm): BSD-4-Clause"), "{}", error);
+        assert!(error.contains("pkg 1.0.0 (npm): (none)"), "{}", error);
+        assert!(check(&packages[..1], &allowed()).is_ok());
+        assert!(check(&packages[..1], &[]).is_err());
+    }
+}
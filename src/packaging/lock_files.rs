@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Report, Result};
+
+/// A third-party package the app depends on
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Package {
+    pub ecosystem: Ecosystem,
+    pub name: String,
+    pub version: String,
+    /// SPDX identifiers or expressions, as declared by the package
+    pub licenses: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Ecosystem {
+    Composer,
+    Npm,
+}
+
+impl Ecosystem {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ecosystem::Composer => "composer",
+            Ecosystem::Npm => "npm",
+        }
+    }
+}
+
+impl Package {
+    /// Package URL as defined by https://github.com/package-url/purl-spec
+    pub fn purl(&self) -> String {
+        format!(
+            "pkg:{}/{}@{}",
+            self.ecosystem.name(),
+            self.name.replace('@', "%40"),
+            self.version
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct ComposerLock {
+    #[serde(default)]
+    packages: Vec<ComposerPackage>,
+}
+
+#[derive(Deserialize)]
+struct ComposerPackage {
+    name: String,
+    version: String,
+    #[serde(default)]
+    license: Vec<String>,
+}
+
+/// `package-lock.json` lists packages by path since version 2 and as a tree
+/// of dependencies before
+#[derive(Deserialize)]
+struct PackageLock {
+    packages: Option<BTreeMap<String, NpmPackage>>,
+    dependencies: Option<BTreeMap<String, NpmDependency>>,
+}
+
+#[derive(Deserialize)]
+struct NpmPackage {
+    name: Option<String>,
+    version: Option<String>,
+    #[serde(flatten)]
+    licenses: NpmLicenses,
+    #[serde(default)]
+    dev: bool,
+    #[serde(default)]
+    link: bool,
+}
+
+#[derive(Deserialize)]
+struct NpmDependency {
+    version: String,
+    #[serde(default)]
+    dev: bool,
+    dependencies: Option<BTreeMap<String, NpmDependency>>,
+}
+
+/// Licenses as declared in `package.json`. Besides an SPDX expression,
+/// older packages use `{"type": "MIT"}` objects or a `licenses` array.
+#[derive(Deserialize, Default)]
+struct NpmLicenses {
+    license: Option<serde_json::Value>,
+    licenses: Option<serde_json::Value>,
+}
+
+impl NpmLicenses {
+    fn into_vec(self) -> Vec<String> {
+        fn collect(value: serde_json::Value, licenses: &mut Vec<String>) {
+            match value {
+                serde_json::Value::String(license) => licenses.push(license),
+                serde_json::Value::Object(mut object) => {
+                    if let Some(license) = object.remove("type") {
+                        collect(license, licenses);
+                    }
+                }
+                serde_json::Value::Array(values) => {
+                    for value in values {
+                        collect(value, licenses);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut licenses = vec![];
+        for value in self.license.into_iter().chain(self.licenses) {
+            collect(value, &mut licenses);
+        }
+        licenses
+    }
+}
+
+/// Reads the licenses of a package installed at the given path. Packages
+/// that aren't installed have no known licenses.
+fn installed_licenses(package_path: &Path) -> Vec<String> {
+    fs::read_to_string(package_path.join("package.json"))
+        .ok()
+        .and_then(|json| serde_json::from_str::<NpmLicenses>(&json).ok())
+        .unwrap_or_default()
+        .into_vec()
+}
+
+/// Production packages of `composer.lock`. Dev packages aren't installed
+/// for packaging.
+fn composer_packages(lock: &str) -> Result<Vec<Package>> {
+    let lock: ComposerLock = serde_json::from_str(lock)?;
+    Ok(lock
+        .packages
+        .into_iter()
+        .map(|package| Package {
+            ecosystem: Ecosystem::Composer,
+            name: package.name,
+            version: package.version,
+            licenses: package.license,
+        })
+        .collect())
+}
+
+/// The dependency tree of version 1 has no licenses, they are read from the
+/// packages installed below `path`
+fn collect_npm_dependencies(
+    dependencies: BTreeMap<String, NpmDependency>,
+    path: &Path,
+    packages: &mut Vec<Package>,
+) {
+    for (name, dependency) in dependencies {
+        if dependency.dev {
+            continue;
+        }
+        let package_path = path.join("node_modules").join(&name);
+        packages.push(Package {
+            ecosystem: Ecosystem::Npm,
+            licenses: installed_licenses(&package_path),
+            name,
+            version: dependency.version,
+        });
+        if let Some(nested) = dependency.dependencies {
+            collect_npm_dependencies(nested, &package_path, packages);
+        }
+    }
+}
+
+/// Production packages of `package-lock.json`, without the root package.
+/// `app_path` is where the packages are installed.
+fn npm_packages(lock: &str, app_path: &Path) -> Result<Vec<Package>> {
+    let lock: PackageLock = serde_json::from_str(lock)?;
+    let mut packages = vec![];
+
+    if let Some(entries) = lock.packages {
+        for (path, package) in entries {
+            if path.is_empty() || package.dev || package.link {
+                continue;
+            }
+            let name = match package.name {
+                Some(name) => name,
+                None => match path.rsplit_once("node_modules/") {
+                    Some((_, name)) => name.to_owned(),
+                    None => continue,
+                },
+            };
+            packages.push(Package {
+                ecosystem: Ecosystem::Npm,
+                name,
+                version: package.version.unwrap_or_default(),
+                licenses: package.licenses.into_vec(),
+            });
+        }
+    } else if let Some(dependencies) = lock.dependencies {
+        collect_npm_dependencies(dependencies, app_path, &mut packages);
+    }
+    Ok(packages)
+}
+
+fn read_lock_file<F>(app_path: &Path, name: &str, parse: F) -> Result<Vec<Package>>
+where
+    F: FnOnce(&str) -> Result<Vec<Package>>,
+{
+    let path = app_path.join(name);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    fs::read_to_string(&path)
+        .map_err(Report::from)
+        .and_then(|lock| parse(&lock))
+        .wrap_err_with(|| format!("Failed to parse {}", name))
+}
+
+/// Reads the packages of the app's `composer.lock` and `package-lock.json`.
+/// Packages that are installed at several places are listed once. Lock
+/// files that can't be parsed are returned as errors, the packages of the
+/// others are read anyway.
+pub fn read_lock_files(app_path: &Path) -> (Vec<Package>, Vec<Report>) {
+    let mut packages = vec![];
+    let mut errors = vec![];
+
+    let lock_files = [
+        read_lock_file(app_path, "composer.lock", composer_packages),
+        read_lock_file(app_path, "package-lock.json", |lock| {
+            npm_packages(lock, app_path)
+        }),
+    ];
+    for lock_file in lock_files {
+        match lock_file {
+            Ok(lock_file_packages) => packages.extend(lock_file_packages),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    packages.sort();
+    packages.dedup();
+    (packages, errors)
+}
+
+/// Like `read_lock_files`, but fails if any lock file can't be parsed
+pub fn read_packages(app_path: &Path) -> Result<Vec<Package>> {
+    let (packages, errors) = read_lock_files(app_path);
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(packages),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn package(ecosystem: Ecosystem, name: &str, version: &str, licenses: &[&str]) -> Package {
+        Package {
+            ecosystem,
+            name: name.to_owned(),
+            version: version.to_owned(),
+            licenses: licenses.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn reads_composer_lock() {
+        let lock = r#"{
+            "packages": [
+                {"name": "guzzlehttp/guzzle", "version": "7.8.1", "license": ["MIT"]}
+            ],
+            "packages-dev": [
+                {"name": "phpunit/phpunit", "version": "9.6.0", "license": ["BSD-3-Clause"]}
+            ]
+        }"#;
+
+        assert_eq!(
+            vec![package(
+                Ecosystem::Composer,
+                "guzzlehttp/guzzle",
+                "7.8.1",
+                &["MIT"]
+            )],
+            composer_packages(lock).unwrap()
+        );
+    }
+
+    #[test]
+    fn reads_package_lock() {
+        let lock = r#"{
+            "lockfileVersion": 3,
+            "packages": {
+                "": {"name": "news", "version": "1.0.0"},
+                "node_modules/@nextcloud/axios": {"version": "2.4.0", "license": "GPL-3.0-or-later"},
+                "node_modules/vue": {"version": "2.7.16", "license": "MIT"},
+                "node_modules/a/node_modules/vue": {"version": "3.4.0", "license": "MIT"},
+                "node_modules/b": {"version": "1.0.0", "license": {"type": "ISC"}},
+                "node_modules/c": {"version": "0.1.0", "licenses": [{"type": "MIT"}, {"type": "Apache-2.0"}]},
+                "node_modules/jest": {"version": "29.0.0", "license": "MIT", "dev": true}
+            }
+        }"#;
+
+        assert_eq!(
+            vec![
+                package(
+                    Ecosystem::Npm,
+                    "@nextcloud/axios",
+                    "2.4.0",
+                    &["GPL-3.0-or-later"]
+                ),
+                package(Ecosystem::Npm, "vue", "3.4.0", &["MIT"]),
+                package(Ecosystem::Npm, "b", "1.0.0", &["ISC"]),
+                package(Ecosystem::Npm, "c", "0.1.0", &["MIT", "Apache-2.0"]),
+                package(Ecosystem::Npm, "vue", "2.7.16", &["MIT"]),
+            ],
+            npm_packages(lock, Path::new("")).unwrap()
+        );
+    }
+
+    #[test]
+    fn reads_legacy_package_lock() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let vue = tmp.path().join("node_modules").join("vue");
+        let de_indent = vue.join("node_modules").join("de-indent");
+        fs::create_dir_all(&de_indent).unwrap();
+        fs::write(vue.join("package.json"), r#"{"license": "MIT"}"#).unwrap();
+        fs::write(
+            de_indent.join("package.json"),
+            r#"{"license": {"type": "MIT"}}"#,
+        )
+        .unwrap();
+        let lock = r#"{
+            "lockfileVersion": 1,
+            "dependencies": {
+                "vue": {"version": "2.6.14", "dependencies": {
+                    "de-indent": {"version": "1.0.2"}
+                }},
+                "jest": {"version": "26.0.0", "dev": true}
+            }
+        }"#;
+
+        assert_eq!(
+            vec![
+                package(Ecosystem::Npm, "vue", "2.6.14", &["MIT"]),
+                package(Ecosystem::Npm, "de-indent", "1.0.2", &["MIT"]),
+            ],
+            npm_packages(lock, tmp.path()).unwrap()
+        );
+        assert_eq!(
+            vec![package(Ecosystem::Npm, "vue", "2.6.14", &[])],
+            npm_packages(
+                r#"{"dependencies": {"vue": {"version": "2.6.14"}}}"#,
+                Path::new("not-installed")
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn reads_other_lock_files_if_one_is_invalid() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::write(
+            tmp.path().join("composer.lock"),
+            r#"{"packages": [{"name": "a/b", "version": "1.0.0", "license": "MIT"}]}"#,
+        )
+        .unwrap();
+        fs::write(
+            tmp.path().join("package-lock.json"),
+            r#"{"packages": {"node_modules/vue": {"version": "2.7.16", "license": "MIT"}}}"#,
+        )
+        .unwrap();
+
+        let (packages, errors) = read_lock_files(tmp.path());
+
+        assert_eq!(
+            vec![package(Ecosystem::Npm, "vue", "2.7.16", &["MIT"])],
+            packages
+        );
+        assert_eq!(1, errors.len());
+        assert_eq!("Failed to parse composer.lock", errors[0].to_string());
+        assert!(read_packages(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn reads_packages_of_app() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::write(
+            tmp.path().join("composer.lock"),
+            r#"{"packages": [{"name": "a/b", "version": "1.0.0"}]}"#,
+        )
+        .unwrap();
+
+        let packages = read_packages(tmp.path()).unwrap();
+
+        assert_eq!(
+            vec![package(Ecosystem::Composer, "a/b", "1.0.0", &[])],
+            packages
+        );
+        assert_eq!("pkg:composer/a/b@1.0.0", packages[0].purl());
+        assert_eq!(
+            "pkg:npm/%40nextcloud/vue@8.0.0",
+            package(Ecosystem::Npm, "@nextcloud/vue", "8.0.0", &[]).purl()
+        );
+    }
+}
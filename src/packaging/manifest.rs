@@ -0,0 +1,141 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Report, Result};
+use sha2::{Digest, Sha256, Sha512};
+
+/// Lists every file of an app archive with its size and SHA-256 hash
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub app_id: String,
+    pub version: String,
+    pub archive: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ManifestEntry {
+    /// Path of the file inside the archive
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Manifest {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)
+            .wrap_err_with(|| format!("Failed to write manifest {:?}", path))
+    }
+}
+
+/// The manifest of `news.tar.gz` is written to `news.manifest.json`
+pub fn manifest_name(archive_name: &str) -> String {
+    format!("{}.manifest.json", archive_name.trim_end_matches(".tar.gz"))
+}
+
+/// Passes the data through and hashes it on the way
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Returns the number of bytes read and their hex encoded hash
+    pub fn finish(self) -> (u64, String) {
+        (self.size, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.size += read as u64;
+        Ok(read)
+    }
+}
+
+fn hash_file<D: Digest + io::Write>(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = D::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Writes `<archive>.sha256` and `<archive>.sha512` in the format of
+/// `sha256sum` and `sha512sum`, so they can be checked with `--check`
+pub fn write_checksums(archive_path: &Path) -> Result<()> {
+    let archive_name = archive_path
+        .file_name()
+        .ok_or_else(|| Report::msg("Invalid archive path"))?
+        .to_string_lossy();
+
+    for (extension, hash) in [
+        ("sha256", hash_file::<Sha256>(archive_path)?),
+        ("sha512", hash_file::<Sha512>(archive_path)?),
+    ] {
+        let checksum_path = archive_path.with_file_name(format!("{}.{}", archive_name, extension));
+        fs::write(&checksum_path, format!("{}  {}\n", hash, archive_name))
+            .wrap_err_with(|| format!("Failed to write checksum {:?}", checksum_path))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn names_manifest_after_archive() {
+        assert_eq!("news.manifest.json", manifest_name("news.tar.gz"));
+        assert_eq!(
+            "news-working-tree.manifest.json",
+            manifest_name("news-working-tree.tar.gz")
+        );
+    }
+
+    #[test]
+    fn hashes_read_data() {
+        let mut reader = HashingReader::new("hello".as_bytes());
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+
+        let (size, hash) = reader.finish();
+
+        assert_eq!(5, size);
+        assert_eq!(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            hash
+        );
+    }
+
+    #[test]
+    fn writes_checksum_files() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let archive_path = tmp.path().join("news.tar.gz");
+        fs::write(&archive_path, "hello").unwrap();
+
+        write_checksums(&archive_path).unwrap();
+
+        assert_eq!(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  news.tar.gz\n",
+            fs::read_to_string(tmp.path().join("news.tar.gz.sha256")).unwrap()
+        );
+        let sha512 = fs::read_to_string(tmp.path().join("news.tar.gz.sha512")).unwrap();
+        assert!(sha512.starts_with("9b71d224bd62f378"));
+        assert!(sha512.ends_with("  news.tar.gz\n"));
+    }
+}
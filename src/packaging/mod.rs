@@ -1,17 +1,75 @@
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 
-use color_eyre::Result;
+use color_eyre::{Report, Result};
+use nextcloud_appinfo::get_appinfo;
 
 mod archive;
 mod artifacts;
+mod build_dir;
+mod build_info;
+mod code_signing;
 mod commands;
+mod dependencies;
+mod diff;
+mod file_rules;
+mod licenses;
+mod lock_files;
+mod manifest;
+mod package_signature;
 mod pipeline;
+mod sbom;
+mod size;
+mod verify;
 
-use crate::packaging::pipeline::App;
+use crate::config::app::get_config;
+use crate::packaging::build_dir::KeepPolicy;
+use crate::packaging::diff::FileChange;
+use crate::packaging::pipeline::{App, Source};
+use crate::packaging::size::{format_size, format_size_delta};
 use color_eyre::eyre::WrapErr;
 
-fn build_archive(app_path: PathBuf) -> Result<()> {
+#[derive(Debug, Default)]
+pub struct PackageOptions {
+    pub shipped: bool,
+    pub git_ref: Option<String>,
+    pub working_tree: bool,
+    pub gitignore: bool,
+    pub keep_build_dir: bool,
+    pub profile: Option<String>,
+    /// Only list the files that would be packaged
+    pub list: bool,
+    /// Skip dependency installation and build commands when listing files
+    pub skip_build: bool,
+}
+
+impl PackageOptions {
+    fn source(&self) -> Source {
+        if self.working_tree {
+            Source::WorkingTree {
+                gitignore: self.gitignore,
+            }
+        } else if let Some(git_ref) = &self.git_ref {
+            Source::GitRef(git_ref.clone())
+        } else {
+            Source::Head
+        }
+    }
+
+    fn keep_policy(&self) -> KeepPolicy {
+        if self.keep_build_dir {
+            KeepPolicy::Always
+        } else {
+            KeepPolicy::Never
+        }
+    }
+}
+
+fn build_archive(app_path: PathBuf, options: PackageOptions) -> Result<()> {
     App::new(app_path)
+        .with_source(options.source())
+        .with_profile(options.profile.clone())
+        .with_keep_build_dir(options.keep_policy())
         .clone()?
         .install_dependencies()
         .wrap_err("Failed to install dependencies")?
@@ -22,8 +80,11 @@ fn build_archive(app_path: PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn build_shipped(app_path: PathBuf) -> Result<()> {
+fn build_shipped(app_path: PathBuf, options: PackageOptions) -> Result<()> {
     App::new(app_path)
+        .with_source(options.source())
+        .with_profile(options.profile.clone())
+        .with_keep_build_dir(options.keep_policy())
         .clone()?
         .install_dependencies()
         .wrap_err("Failed to install dependencies")?
@@ -34,12 +95,203 @@ fn build_shipped(app_path: PathBuf) -> Result<()> {
     Ok(())
 }
 
-pub fn package_app(app_path: &PathBuf, shipped: bool) -> Result<()> {
-    let app_path = app_path.clone();
+fn list_files(app_path: PathBuf, options: PackageOptions) -> Result<()> {
+    let cloned = App::new(app_path)
+        .with_source(options.source())
+        .with_profile(options.profile.clone())
+        .with_keep_build_dir(options.keep_policy())
+        .clone()?;
+    let built = if options.skip_build {
+        cloned.skip_build()
+    } else {
+        cloned
+            .install_dependencies()
+            .wrap_err("Failed to install dependencies")?
+            .build()
+            .wrap_err("Failed to build app")?
+    };
+    let file_list = built.into_file_list()?;
+
+    for (path, size) in file_list.included() {
+        println!("{:>10}  {}", format_size(*size), path.to_string_lossy());
+    }
+    println!(
+        "Included files: {} ({}), excluded files: {}",
+        file_list.included().len(),
+        format_size(file_list.total_size()),
+        file_list.excluded()
+    );
+    Ok(())
+}
+
+pub fn package_app(app_path: &Path, options: PackageOptions) -> Result<()> {
+    let app_path = app_path.to_path_buf();
+
+    if options.list {
+        list_files(app_path, options)
+    } else if options.shipped {
+        build_shipped(app_path, options)
+    } else {
+        build_archive(app_path, options)
+    }
+}
+
+pub fn remove_kept_build_dirs(app_path: &Path) -> Result<usize> {
+    build_dir::remove_kept(app_path)
+}
+
+/// Removes all but the newest `keep` builds from `build/artifacts`
+pub fn prune_artifacts(app_path: &Path, keep: usize) -> Result<usize> {
+    artifacts::prune(&app_path.join("build").join("artifacts"), keep)
+}
+
+/// Compares two archives, the new one defaults to the one in
+/// `build/artifacts`
+pub fn diff_packages(app_path: &Path, old_path: &Path, new_path: Option<&Path>) -> Result<()> {
+    let new_path = match new_path {
+        Some(new_path) => new_path.to_path_buf(),
+        None => find_archive(app_path)?,
+    };
+    let diff = diff::diff_archives(old_path, &new_path)?;
 
-    if shipped {
-        build_shipped(app_path)
+    println!("Comparing {:?} with {:?}", old_path, new_path);
+    if diff.files.is_empty() {
+        println!("No files changed.");
     } else {
-        build_archive(app_path)
+        println!("Files:");
+        for (path, change) in &diff.files {
+            let marker = match change {
+                FileChange::Added { .. } => "+",
+                FileChange::Removed { .. } => "-",
+                FileChange::Changed { .. } => "~",
+            };
+            println!(
+                "  {} {} ({})",
+                marker,
+                path.to_string_lossy(),
+                format_size_delta(change.size_delta())
+            );
+        }
+        let count = |f: fn(&FileChange) -> bool| diff.files.iter().filter(|(_, c)| f(c)).count();
+        println!(
+            "Added: {}, removed: {}, changed: {}, size: {}",
+            count(|c| matches!(c, FileChange::Added { .. })),
+            count(|c| matches!(c, FileChange::Removed { .. })),
+            count(|c| matches!(c, FileChange::Changed { .. })),
+            format_size_delta(diff.files.iter().map(|(_, c)| c.size_delta()).sum())
+        );
     }
+
+    if !diff.info.is_empty() {
+        println!("info.xml:");
+        for change in &diff.info {
+            println!(
+                "  {}: {} -> {}",
+                change.field,
+                change.old.as_deref().unwrap_or("(none)"),
+                change.new.as_deref().unwrap_or("(none)")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Lists the third-party packages of the app with their licenses. Fails if
+/// an allowlist is configured and a license is not on it.
+pub fn list_licenses(app_path: &Path, profile: Option<&str>) -> Result<()> {
+    let config = get_config(app_path, profile)?.unwrap_or_default();
+    let allowed = config.package().licenses().allowed();
+    let packages = lock_files::read_packages(app_path)?;
+
+    let width = packages
+        .iter()
+        .map(|package| package.name.len() + package.version.len() + 1)
+        .max()
+        .unwrap_or(0);
+    for package in &packages {
+        let marker = if allowed.is_empty() || licenses::is_package_allowed(package, allowed) {
+            ""
+        } else {
+            "  (not allowed)"
+        };
+        println!(
+            "{:<8}  {:<width$}  {}{}",
+            package.ecosystem.name(),
+            format!("{}@{}", package.name, package.version),
+            licenses::license_text(package),
+            marker,
+            width = width
+        );
+    }
+    println!("Packages: {}", packages.len());
+
+    if allowed.is_empty() {
+        println!("No allowed licenses configured, skipping the license check.");
+        return Ok(());
+    }
+    licenses::check(&packages, allowed)?;
+    println!("All licenses are allowed.");
+    Ok(())
+}
+
+/// Finds the newest archive in `build/artifacts`
+fn find_archive(app_path: &Path) -> Result<PathBuf> {
+    let artifacts_path = app_path.join("build").join("artifacts");
+    match artifacts::builds(&artifacts_path)?.first() {
+        Some(build) => Ok(build.archive().clone()),
+        None => Err(Report::msg(format!(
+            "No app archive found in {:?}, run krankerl package first",
+            artifacts_path
+        ))),
+    }
+}
+
+/// Signs the given archive with the private key at `key_path`
+pub fn sign_archive(archive_path: &Path, key_path: &Path) -> Result<String> {
+    let key = package_signature::private_key(key_path)?;
+    let mut archive = File::open(archive_path)
+        .wrap_err_with(|| format!("Failed to open archive {:?}", archive_path))?;
+    package_signature::sign(&mut archive, &key)
+}
+
+/// Verifies the signature of the given archive, or the one in
+/// `build/artifacts`. The signature is given as is or as a file containing
+/// it. Returns the path of the verified archive.
+pub fn verify_signature(
+    app_path: &Path,
+    archive_path: Option<&Path>,
+    signature: &str,
+    certificate_path: &Path,
+) -> Result<PathBuf> {
+    let archive_path = match archive_path {
+        Some(archive_path) => archive_path.to_path_buf(),
+        None => find_archive(app_path)?,
+    };
+    let signature = if Path::new(signature).is_file() {
+        fs::read_to_string(signature)
+            .wrap_err_with(|| format!("Failed to read signature {:?}", signature))?
+    } else {
+        signature.to_owned()
+    };
+    let certificate = fs::read_to_string(certificate_path)
+        .wrap_err_with(|| format!("Failed to read certificate {:?}", certificate_path))?;
+    let public_key = package_signature::public_key(&certificate)?;
+
+    let mut archive = File::open(&archive_path)
+        .wrap_err_with(|| format!("Failed to open archive {:?}", archive_path))?;
+    package_signature::verify(&mut archive, &signature, &public_key)?;
+    Ok(archive_path)
+}
+
+/// Verifies the given archive, or the one in `build/artifacts`, against the
+/// app at `app_path`. Returns the path of the verified archive.
+pub fn verify_archive(app_path: &Path, archive_path: Option<&Path>) -> Result<PathBuf> {
+    let app_info = get_appinfo(app_path).wrap_err("Failed to parse info.xml")?;
+    let archive_path = match archive_path {
+        Some(archive_path) => archive_path.to_path_buf(),
+        None => find_archive(app_path)?,
+    };
+
+    verify::verify_archive(&archive_path, &app_info)?;
+    Ok(archive_path)
 }
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Report, Result};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha512};
+use simple_asn1::ASN1Block;
+
+fn invalid_certificate() -> Report {
+    Report::msg("Invalid certificate, expected an X.509 certificate with an RSA key")
+}
+
+/// Extracts the `SubjectPublicKeyInfo` of a DER encoded X.509 certificate
+fn certificate_public_key(der: &[u8]) -> Result<RsaPublicKey> {
+    let blocks = simple_asn1::from_der(der).map_err(|_| invalid_certificate())?;
+    let tbs_certificate = match blocks.first() {
+        Some(ASN1Block::Sequence(_, certificate)) => match certificate.first() {
+            Some(ASN1Block::Sequence(_, tbs_certificate)) => tbs_certificate,
+            _ => return Err(invalid_certificate()),
+        },
+        _ => return Err(invalid_certificate()),
+    };
+    // The version is optional, it is followed by the serial number,
+    // signature algorithm, issuer, validity and subject
+    let skip = match tbs_certificate.first() {
+        Some(ASN1Block::Explicit(..)) => 6,
+        _ => 5,
+    };
+    let public_key_info = tbs_certificate.get(skip).ok_or_else(invalid_certificate)?;
+    let public_key_der = simple_asn1::to_der(public_key_info).map_err(|_| invalid_certificate())?;
+    RsaPublicKey::from_public_key_der(&public_key_der).map_err(|_| invalid_certificate())
+}
+
+/// Reads the public key of a PEM encoded certificate. Plain public keys are
+/// accepted as well.
+pub fn public_key(pem: &str) -> Result<RsaPublicKey> {
+    let (label, der) = rsa::pkcs8::der::pem::decode_vec(pem.trim().as_bytes())
+        .map_err(|_| invalid_certificate())?;
+    match label {
+        "CERTIFICATE" => certificate_public_key(&der),
+        "RSA PUBLIC KEY" => RsaPublicKey::from_pkcs1_der(&der).map_err(|_| invalid_certificate()),
+        _ => RsaPublicKey::from_public_key_der(&der).map_err(|_| invalid_certificate()),
+    }
+}
+
+/// Reads a PEM encoded private key, in PKCS #8 or PKCS #1 format
+pub fn private_key(key_path: &Path) -> Result<RsaPrivateKey> {
+    let key = fs::read_to_string(key_path)
+        .wrap_err_with(|| format!("Failed to read private key {:?}", key_path))?;
+    RsaPrivateKey::from_pkcs8_pem(&key)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&key))
+        .map_err(|e| Report::msg(format!("Invalid private key {:?}: {}", key_path, e)))
+}
+
+fn sha512<R: Read>(package: &mut R) -> Result<Vec<u8>> {
+    let mut hasher = Sha512::new();
+    io::copy(package, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Signs a package for the app store, returns the base64 encoded RSA
+/// PKCS #1 v1.5 signature of its SHA-512 hash. The private key operation is
+/// blinded, so its timing doesn't depend on the key.
+pub fn sign<R: Read>(package: &mut R, key: &RsaPrivateKey) -> Result<String> {
+    let signature = key
+        .sign_with_rng(
+            &mut rand::thread_rng(),
+            Pkcs1v15Sign::new::<Sha512>(),
+            &sha512(package)?,
+        )
+        .map_err(|e| Report::msg(format!("Failed to sign package: {}", e)))?;
+    Ok(base64::Engine::encode(
+        &base64::engine::general_purpose::STANDARD,
+        signature,
+    ))
+}
+
+/// Checks a base64 encoded package signature as created by
+/// `krankerl sign --package`, an RSA PKCS #1 v1.5 signature of the
+/// package's SHA-512 hash
+pub fn verify<R: Read>(package: &mut R, signature: &str, public_key: &RsaPublicKey) -> Result<()> {
+    let signature =
+        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, signature.trim())
+            .map_err(|_| Report::msg("Invalid signature, expected base64"))?;
+
+    public_key
+        .verify(Pkcs1v15Sign::new::<Sha512>(), &sha512(package)?, &signature)
+        .map_err(|_| Report::msg("Signature does not match the package and certificate"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use rsa::traits::PublicKeyParts;
+
+    use super::*;
+
+    fn assets_path() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("assets")
+            .join("code-signing")
+    }
+
+    fn certificate_key() -> RsaPublicKey {
+        public_key(&fs::read_to_string(assets_path().join("recommendations.crt")).unwrap()).unwrap()
+    }
+
+    fn sign_content(content: &[u8]) -> String {
+        let key = private_key(&assets_path().join("recommendations.key")).unwrap();
+        sign(&mut &content[..], &key).unwrap()
+    }
+
+    #[test]
+    fn reads_public_key_of_certificate() {
+        assert_eq!(256, certificate_key().size());
+        assert!(public_key("not a certificate").is_err());
+    }
+
+    /// `app.info.xml.sig` was created with
+    /// `openssl dgst -sha512 -sign recommendations.key app/appinfo/info.xml`
+    #[test]
+    fn signs_like_openssl() {
+        let expected = fs::read_to_string(assets_path().join("app.info.xml.sig")).unwrap();
+        let package = fs::read(assets_path().join("app").join("appinfo").join("info.xml")).unwrap();
+
+        assert_eq!(expected.trim(), sign_content(&package));
+    }
+
+    #[test]
+    fn verifies_package_signature() {
+        let signature = sign_content(b"package");
+
+        assert!(verify(&mut &b"package"[..], &signature, &certificate_key()).is_ok());
+        assert!(verify(&mut &b"tampered"[..], &signature, &certificate_key()).is_err());
+        assert!(verify(&mut &b"package"[..], "invalid", &certificate_key()).is_err());
+    }
+}
@@ -1,17 +1,23 @@
 use std::fs::{copy, create_dir_all, File};
 use std::path::{Path, PathBuf};
 
-use color_eyre::Result;
-use flate2::write::GzEncoder;
-use flate2::Compression;
+use color_eyre::{eyre::WrapErr, Report, Result};
+use flate2::{Compression, GzBuilder};
 use ignore::{DirEntry, WalkBuilder};
 use nextcloud_appinfo::{get_appinfo, AppInfo};
 use pathdiff::diff_paths;
-use tempdir::TempDir;
 
-use crate::config;
+use crate::config::app::{get_config, is_artifact_name, AppConfig, PackageConfig, PackageStep};
+use crate::packaging::build_dir::{BuildDir, KeepPolicy};
+use crate::packaging::build_info::BuildInfo;
+use crate::packaging::code_signing::{self, Signer};
 use crate::packaging::commands::{self, PackageCommands};
-use crate::packaging::{archive, artifacts};
+use crate::packaging::file_rules::FileRules;
+use crate::packaging::lock_files::Package;
+use crate::packaging::manifest::{self, Manifest};
+use crate::packaging::{
+    archive, artifacts, dependencies, licenses, lock_files, sbom, size, verify,
+};
 
 fn tmp_app_path(base: &Path, app_id: &str) -> PathBuf {
     let mut buf = base.to_path_buf();
@@ -19,51 +25,189 @@ fn tmp_app_path(base: &Path, app_id: &str) -> PathBuf {
     buf
 }
 
+/// The copied working tree has no git metadata, its base commit is the one
+/// checked out in the source directory
+fn git_repo_path<'a>(app: &'a App, build_path: &'a Path) -> &'a Path {
+    if app.is_working_tree() {
+        &app.source_path
+    } else {
+        build_path
+    }
+}
+
+/// Sets up the given commands to log their output and to know about the app
+/// and the build
+fn app_commands(
+    cmds: &[PackageStep],
+    app: &App,
+    app_info: &AppInfo,
+    build_path: &Path,
+) -> Result<commands::CommandList> {
+    let git_commit = artifacts::head_commit(git_repo_path(app, build_path))?
+        .map(|id| id.to_string())
+        .unwrap_or_default();
+
+    Ok(commands::CommandList::new(cmds.to_vec())
+        .var("app_id", app_info.id().as_str())
+        .var("version", app_info.version().to_string())
+        .var("build_dir", build_path.to_string_lossy())
+        .var(
+            "source_dir",
+            app.source_path.canonicalize()?.to_string_lossy(),
+        )
+        .var("git_commit", git_commit)
+        .log_to(&commands::log_path(&app.source_path)))
+}
+
+/// The state of the app that gets packaged
+#[derive(Debug)]
+pub enum Source {
+    Head,
+    GitRef(String),
+    WorkingTree { gitignore: bool },
+}
+
 pub struct App {
     source_path: PathBuf,
+    source: Source,
+    profile: Option<String>,
+    keep_build_dir: KeepPolicy,
 }
 
 impl App {
     pub fn new(source_path: PathBuf) -> Self {
         App {
-            source_path: source_path,
+            source_path,
+            source: Source::Head,
+            profile: None,
+            keep_build_dir: KeepPolicy::Never,
         }
     }
 
+    pub fn with_profile(self, profile: Option<String>) -> Self {
+        App { profile, ..self }
+    }
+
+    pub fn with_source(self, source: Source) -> Self {
+        App { source, ..self }
+    }
+
+    pub fn with_keep_build_dir(self, keep_build_dir: KeepPolicy) -> Self {
+        App {
+            keep_build_dir,
+            ..self
+        }
+    }
+
+    fn is_working_tree(&self) -> bool {
+        matches!(self.source, Source::WorkingTree { .. })
+    }
+
     pub fn clone(self) -> Result<ClonedApp> {
-        println!("Cloning app");
+        let source_info = get_appinfo(&self.source_path)?;
+        let log = commands::start_log(&self.source_path)?;
+        println!("Logging command output to {:?}", log);
+        let mut tmp = BuildDir::new(&self.source_path, self.keep_build_dir)?;
+        // Failed clones and checkouts are worth inspecting too, so the current
+        // config decides before anything is cloned. Config errors are reported
+        // once the config of the packaged source is loaded.
+        if let Ok(Some(config)) = get_config(&self.source_path, self.profile.as_deref()) {
+            if config.package().keep_build_dir_on_failure() {
+                tmp.keep_on_failure();
+            }
+        }
+        let clone_path = tmp_app_path(tmp.path(), source_info.id());
 
-        let app_info = get_appinfo(&self.source_path)?;
-        let tmp = TempDir::new("krankerl")?;
-        artifacts::clone_app(&self.source_path, &tmp_app_path(tmp.path(), app_info.id()))?;
+        if let Source::WorkingTree { gitignore } = self.source {
+            println!("Copying working tree");
+            artifacts::copy_working_tree(&self.source_path, &clone_path, gitignore)?;
+            println!("Working tree copied to {:?}", tmp.path());
+        } else {
+            println!("Cloning app");
+            artifacts::clone_app(&self.source_path, &clone_path)?;
+            println!("App cloned to {:?}", tmp.path());
+        }
 
-        println!("App cloned to {:?}", tmp.path());
-        Ok(ClonedApp::new(self, app_info, tmp))
+        // A checked out ref brings its own app info and config
+        let (app_info, config_path) = match &self.source {
+            Source::GitRef(git_ref) => {
+                let commit = artifacts::checkout(&clone_path, git_ref)?;
+                println!("Checked out {} at commit {}", git_ref, commit);
+
+                let app_info = get_appinfo(&clone_path)?;
+                if app_info.id() != source_info.id() {
+                    return Err(Report::msg(format!(
+                        "App id <{}> of {} does not match the current app id <{}>",
+                        app_info.id(),
+                        git_ref,
+                        source_info.id()
+                    )));
+                }
+                (app_info, clone_path.clone())
+            }
+            _ => (source_info, self.source_path.clone()),
+        };
+        if !self.is_working_tree() {
+            artifacts::update_submodules(&clone_path)?;
+        }
+        let (config, default_config) = match get_config(&config_path, self.profile.as_deref())? {
+            Some(config) => (config, false),
+            None => (AppConfig::default(), true),
+        };
+        if config.package().keep_build_dir_on_failure() {
+            tmp.keep_on_failure();
+        }
+
+        Ok(ClonedApp::new(self, app_info, config, default_config, tmp))
     }
 }
 
 pub struct ClonedApp {
     app: App,
     app_info: AppInfo,
-    tmp_dir: TempDir,
+    config: AppConfig,
+    default_config: bool,
+    tmp_dir: BuildDir,
 }
 
 impl ClonedApp {
-    pub fn new(app: App, app_info: AppInfo, tmp_dir: TempDir) -> Self {
+    pub fn new(
+        app: App,
+        app_info: AppInfo,
+        config: AppConfig,
+        default_config: bool,
+        tmp_dir: BuildDir,
+    ) -> Self {
         ClonedApp {
-            app: app,
-            app_info: app_info,
-            tmp_dir: tmp_dir,
+            app,
+            app_info,
+            config,
+            default_config,
+            tmp_dir,
         }
     }
 
+    /// Leaves the clone as it is, without dependencies and build commands
+    pub fn skip_build(self) -> BuiltApp {
+        println!("Build skipped");
+        BuiltApp::new(AppWithDependencies::new(self))
+    }
+
     pub fn install_dependencies(self) -> Result<AppWithDependencies> {
-        // TODO: automatically install npm and composer dependencies
-        // progress
-        //    .as_ref()
-        //    .map(|prog| prog.set_message("Installing dependencies"));
+        let dependency_config = self.config.package().dependencies();
+        if !dependency_config.install() {
+            println!("Dependency installation skipped");
+            return Ok(AppWithDependencies::new(self));
+        }
 
-        println!("Dependency installation skipped");
+        println!("Installing dependencies");
+        dependencies::install(
+            dependency_config,
+            &tmp_app_path(self.tmp_dir.path(), self.app_info.id()),
+            &commands::log_path(&self.app.source_path),
+        )?;
+
+        println!("Dependencies installed");
         Ok(AppWithDependencies::new(self))
     }
 }
@@ -71,7 +215,9 @@ impl ClonedApp {
 pub struct AppWithDependencies {
     app: App,
     app_info: AppInfo,
-    tmp_dir: TempDir,
+    config: AppConfig,
+    default_config: bool,
+    tmp_dir: BuildDir,
 }
 
 impl AppWithDependencies {
@@ -79,6 +225,8 @@ impl AppWithDependencies {
         AppWithDependencies {
             app: clone.app,
             app_info: clone.app_info,
+            config: clone.config,
+            default_config: clone.default_config,
             tmp_dir: clone.tmp_dir,
         }
     }
@@ -86,16 +234,19 @@ impl AppWithDependencies {
     pub fn build(self) -> Result<BuiltApp> {
         println!("Building app");
 
-        let opt_config = config::app::get_config(&self.app.source_path)?;
-        let (config, default) = match opt_config {
-            Some(config) => (config, false),
-            None => (config::app::AppConfig::default(), true),
-        };
-        let cmds = commands::CommandList::from(config.package());
-        cmds.execute(&tmp_app_path(&self.tmp_dir.path(), self.app_info.id()))?;
+        let app_path = tmp_app_path(self.tmp_dir.path(), self.app_info.id());
+        let cmds = app_commands(
+            self.config.package().before_cmds(),
+            &self.app,
+            &self.app_info,
+            &app_path,
+        )?;
+        cmds.execute(&app_path)?;
 
-        if default {
+        if self.default_config {
             println!("App built with default config");
+        } else if let Some(profile) = self.config.profile() {
+            println!("App built with profile {}", profile);
         } else {
             println!("App built");
         }
@@ -106,7 +257,8 @@ impl AppWithDependencies {
 pub struct BuiltApp {
     app: App,
     app_info: AppInfo,
-    tmp_dir: TempDir,
+    config: AppConfig,
+    tmp_dir: BuildDir,
 }
 
 impl BuiltApp {
@@ -114,77 +266,304 @@ impl BuiltApp {
         BuiltApp {
             app: with_deps.app,
             app_info: with_deps.app_info,
+            config: with_deps.config,
             tmp_dir: with_deps.tmp_dir,
         }
     }
 
-    pub fn into_archive(self) -> Result<AppArchive> {
-        let mut compressed_archive_path = self.app.source_path.to_path_buf();
-        compressed_archive_path.push("build");
-        compressed_archive_path.push("artifacts");
-        artifacts::clear(&compressed_archive_path)?;
+    fn artifact_name(&self) -> Result<String> {
+        let name = self
+            .config
+            .package()
+            .artifact_name()
+            .replace("{app_id}", self.app_info.id())
+            .replace("{version}", &self.app_info.version().to_string())
+            .replace(
+                "{profile}",
+                self.config
+                    .profile()
+                    .map(String::as_str)
+                    .unwrap_or("default"),
+            );
+        if !is_artifact_name(&name) {
+            return Err(Report::msg(format!(
+                "Invalid artifact name <{}>, it must not contain path separators or ..",
+                name
+            )));
+        }
+        Ok(name)
+    }
+
+    /// Reads the packages of the app's lock files for the license check and
+    /// the SBOM. Lock files that can't be parsed fail the license check, the
+    /// SBOM is written without their packages otherwise.
+    fn read_packages(&self, app_path: &Path) -> Result<Vec<Package>> {
+        let (packages, errors) = lock_files::read_lock_files(app_path);
+        for e in errors {
+            if self.config.package().licenses().check() {
+                return Err(e);
+            }
+            println!("Warning: {:#}, its packages are left out of the SBOM", e);
+        }
+        Ok(packages)
+    }
+
+    /// Checks the licenses of the app's packages if enabled
+    fn check_licenses(&self, packages: &[Package]) -> Result<()> {
+        let config = self.config.package().licenses();
+        if !config.check() {
+            return Ok(());
+        }
+        licenses::check(packages, config.allowed())?;
+        println!("Licenses checked");
+        Ok(())
+    }
+
+    fn write_build_info(&self, app_path: &Path) -> Result<()> {
+        if !self.config.package().build_info() {
+            return Ok(());
+        }
+        BuildInfo::new(
+            git_repo_path(&self.app, app_path),
+            self.app.is_working_tree(),
+            self.config.profile(),
+        )?
+        .write(app_path)
+        .wrap_err("Failed to write build info")
+    }
+
+    /// Writes `appinfo/signature.json` for the server's code integrity
+    /// check if enabled. Has to run after all other changes to the app.
+    fn sign_code(&self, app_path: &Path) -> Result<()> {
+        let config = self.config.package().code_signing();
+        if !config.enabled() {
+            return Ok(());
+        }
+
+        let (default_key, default_certificate) =
+            code_signing::default_key_paths(self.app_info.id())?;
+        let key = config
+            .key()
+            .map(|key| self.app.source_path.join(key))
+            .unwrap_or(default_key);
+        let certificate = config
+            .certificate()
+            .map(|certificate| self.app.source_path.join(certificate))
+            .unwrap_or(default_certificate);
+        let signer = Signer::load(&key, &certificate)?;
+
+        let mut files = vec![];
+        for entry in build_file_list(app_path, self.config.package())? {
+            if entry.metadata()?.is_dir() {
+                continue;
+            }
+            if let Some(normalized) = diff_paths(entry.path(), app_path) {
+                files.push(normalized);
+            }
+        }
+        let count =
+            code_signing::sign_app(app_path, &files, &signer).wrap_err("Failed to sign the app")?;
+        println!("Signed {} files for the code integrity check", count);
+        Ok(())
+    }
+
+    /// Carries previous builds over into the staged artifacts, so the
+    /// configured number of builds is left once they are replaced
+    fn keep_previous_builds(&self, artifacts_path: &Path, staging: &Path) -> Result<()> {
+        let count = self.config.package().keep_artifacts() - 1;
+        if count > 0 {
+            artifacts::keep_previous(artifacts_path, staging, count)?;
+        }
+        Ok(())
+    }
+
+    fn run_after_cmds(&self, artifact_path: &Path) -> Result<()> {
+        let after_cmds = self.config.package().after_cmds();
+        if after_cmds.is_empty() {
+            return Ok(());
+        }
+
+        let artifact_path = artifact_path.canonicalize()?;
+        let app_path = tmp_app_path(self.tmp_dir.path(), self.app_info.id());
+        app_commands(after_cmds, &self.app, &self.app_info, &app_path)?
+            .var("artifact", artifact_path.to_string_lossy())
+            .execute(&self.app.source_path)
+            .wrap_err("Failed to run post-packaging commands")
+    }
+
+    pub fn into_archive(mut self) -> Result<AppArchive> {
+        let mut artifacts_path = self.app.source_path.to_path_buf();
+        artifacts_path.push("build");
+        artifacts_path.push("artifacts");
+        let staging = artifacts::stage(&artifacts_path)?;
 
-        compressed_archive_path.push(format!("{}.tar.gz", self.app_info.id()));
+        let archive_name = if self.app.is_working_tree() {
+            // Mark the archive so it can't be mistaken for (and signed as) a release
+            println!("Warning: the app is packaged from uncommitted changes");
+            format!("{}-working-tree.tar.gz", self.artifact_name()?)
+        } else {
+            format!("{}.tar.gz", self.artifact_name()?)
+        };
+        let compressed_archive_path = artifacts_path.join(&archive_name);
         println!(
             "Writing compressed app archive to {:?}...",
             compressed_archive_path
         );
 
-        let gz_archive_file = File::create(&compressed_archive_path)?;
-        let encoder = GzEncoder::new(gz_archive_file, Compression::default());
-
         let app_path = tmp_app_path(self.tmp_dir.path(), self.app_info.id());
+        let packages = self.read_packages(&app_path)?;
+        self.check_licenses(&packages)?;
+        self.write_build_info(&app_path)?;
+        self.sign_code(&app_path)?;
+        let mtime = artifacts::source_date_epoch(&app_path)?;
+
+        let gz_archive_file = File::create(staging.path().join(&archive_name))?;
+        let encoder = GzBuilder::new()
+            .mtime(0)
+            .operating_system(255)
+            .write(gz_archive_file, Compression::default());
+
         {
             let base = Path::new(self.app_info.id());
 
-            let file_list = build_file_list(&app_path);
-            let encoder = archive::build_app_archive(&base, &app_path, file_list, encoder)?;
+            let file_list = build_file_list(&app_path, self.config.package())?;
+            let (encoder, files) =
+                archive::build_app_archive(base, &app_path, file_list, mtime, encoder)?;
             encoder.finish()?;
+
+            let archive_size = staging.path().join(&archive_name).metadata()?.len();
+            size::print_report(&files, archive_size);
+            size::check_limits(&files, archive_size, self.config.package().limits())?;
+
+            let manifest = Manifest {
+                app_id: self.app_info.id().clone(),
+                version: self.app_info.version().to_string(),
+                archive: archive_name.clone(),
+                files,
+            };
+            manifest.write(&staging.path().join(manifest::manifest_name(&archive_name)))?;
+            sbom::write(
+                &staging.path().join(sbom::sbom_name(&archive_name)),
+                &app_path,
+                &self.app_info,
+                &packages,
+            )?;
+            manifest::write_checksums(&staging.path().join(&archive_name))?;
         }
+        if self.config.package().verify() {
+            verify::verify_archive(&staging.path().join(&archive_name), &self.app_info)?;
+            println!("App archive verified");
+        }
+        self.keep_previous_builds(&artifacts_path, staging.path())?;
+        artifacts::replace(staging, &artifacts_path)?;
 
         println!("Packaged app as {:?}", compressed_archive_path);
+        self.run_after_cmds(&compressed_archive_path)?;
+        self.tmp_dir.succeed();
 
         Ok(AppArchive::new(self))
     }
 
-    pub fn into_shipped(self) -> Result<ShippedApp> {
-        let mut ship_path = self.app.source_path.to_path_buf();
-        ship_path.push("build");
-        ship_path.push("artifacts");
-        artifacts::clear(&ship_path)?;
-        ship_path.push(self.app_info.id());
+    /// Lists the files that would be packaged instead of packaging them
+    pub fn into_file_list(mut self) -> Result<FileList> {
+        let app_path = tmp_app_path(self.tmp_dir.path(), self.app_info.id());
+        self.write_build_info(&app_path)?;
+
+        let mut included = vec![];
+        for entry in build_file_list(&app_path, self.config.package())? {
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                continue;
+            }
+            if let Some(normalized) = diff_paths(entry.path(), &app_path) {
+                included.push((normalized, metadata.len()));
+            }
+        }
+        included.sort();
+
+        let all_files = WalkBuilder::new(&app_path)
+            .standard_filters(false)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| !t.is_dir()).unwrap_or(false))
+            .count();
+
+        self.tmp_dir.succeed();
+        Ok(FileList {
+            excluded: all_files.saturating_sub(included.len()),
+            included,
+        })
+    }
+
+    pub fn into_shipped(mut self) -> Result<ShippedApp> {
+        let mut artifacts_path = self.app.source_path.to_path_buf();
+        artifacts_path.push("build");
+        artifacts_path.push("artifacts");
+        let staging = artifacts::stage(&artifacts_path)?;
+        let ship_path = artifacts_path.join(self.app_info.id());
         println!("Writing app files to {:?}...", ship_path);
+        if self.app.is_working_tree() {
+            println!("Warning: the app is packaged from uncommitted changes");
+        }
 
         let app_path = tmp_app_path(self.tmp_dir.path(), self.app_info.id());
+        if self.config.package().licenses().check() {
+            self.check_licenses(&lock_files::read_packages(&app_path)?)?;
+        }
+        self.write_build_info(&app_path)?;
+        self.sign_code(&app_path)?;
         {
-            for entry in build_file_list(&app_path) {
+            let staged_ship_path = staging.path().join(self.app_info.id());
+            for entry in build_file_list(&app_path, self.config.package())? {
                 if !entry.metadata().unwrap().is_dir() {
                     let entry_path = entry.path();
-                    if let Some(normalized) = diff_paths(&entry_path, &app_path) {
-                        let mut file_path = ship_path.clone();
+                    if let Some(normalized) = diff_paths(entry_path, &app_path) {
+                        let mut file_path = staged_ship_path.clone();
                         file_path.push(&normalized);
                         if let Some(parent) = file_path.parent() {
                             create_dir_all(parent)?;
                         }
-                        copy(&entry.path(), &file_path)?;
+                        copy(entry.path(), &file_path)?;
                     }
                 }
             }
         }
+        self.keep_previous_builds(&artifacts_path, staging.path())?;
+        artifacts::replace(staging, &artifacts_path)?;
 
         println!("App directory created at {:?}", ship_path);
+        self.run_after_cmds(&ship_path)?;
+        self.tmp_dir.succeed();
         Ok(ShippedApp::new(self))
     }
 }
 
-fn build_file_list(build_path: &Path) -> Vec<DirEntry> {
-    WalkBuilder::new(build_path)
+fn build_file_list(build_path: &Path, config: &PackageConfig) -> Result<Vec<DirEntry>> {
+    let rules = FileRules::new(build_path, config.include(), config.exclude())?;
+    let root = build_path.to_path_buf();
+
+    // The git metadata of the clone and its submodules is specific to this
+    // build and never part of the app
+    Ok(WalkBuilder::new(build_path)
         .standard_filters(false)
-        .add_custom_ignore_filename(".nextcloudignore")
+        .add_custom_ignore_filename(config.ignore_file())
+        .filter_entry(move |entry| {
+            if entry.file_name() == ".git" {
+                return false;
+            }
+            match entry.path().strip_prefix(&root) {
+                Ok(path) if path.as_os_str().is_empty() => true,
+                Ok(path) => {
+                    let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                    rules.is_packaged(path, is_dir)
+                }
+                Err(_) => true,
+            }
+        })
         .build()
-        .into_iter()
         .map(|e| e.unwrap())
-        .collect()
+        .collect())
 }
 
 pub struct AppArchive {}
@@ -203,6 +582,28 @@ impl ShippedApp {
     }
 }
 
+/// The files that would be packaged, relative to the app's root directory
+/// and with their sizes
+pub struct FileList {
+    included: Vec<(PathBuf, u64)>,
+    excluded: usize,
+}
+
+impl FileList {
+    pub fn included(&self) -> &Vec<(PathBuf, u64)> {
+        &self.included
+    }
+
+    /// Number of files in the build directory that are not packaged
+    pub fn excluded(&self) -> usize {
+        self.excluded
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.included.iter().map(|(_, size)| size).sum()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::{Path, PathBuf};
@@ -257,6 +658,24 @@ mod tests {
         assert!(cloned_app_dir.exists());
     }
 
+    #[test]
+    fn excludes_git_metadata_of_clone() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app = App::new(get_test_app_path(dir.path()));
+        let clone = app.clone().unwrap();
+        let app_path = tmp_app_path(clone.tmp_dir.path(), APP_ID);
+        assert!(app_path.join(".git").exists());
+
+        let file_list = build_file_list(&app_path, &PackageConfig::default()).unwrap();
+
+        assert!(file_list
+            .iter()
+            .any(|e| e.path().ends_with("appinfo/info.xml")));
+        assert!(!file_list
+            .iter()
+            .any(|e| e.path().starts_with(app_path.join(".git"))));
+    }
+
     #[test]
     fn install_app_dependencies() {
         let dir = create_test_app_dir(MINIMALIST_APP);
@@ -318,4 +737,827 @@ mod tests {
             "package.json should not be copied"
         );
     }
+
+    fn build_archive_bytes() -> Vec<u8> {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app = App::new(get_test_app_path(dir.path()));
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let mut archive_path = get_test_app_path(dir.path());
+        archive_path.push("build");
+        archive_path.push("artifacts");
+        archive_path.push(format!("{}.tar.gz", APP_ID));
+        std::fs::read(archive_path).unwrap()
+    }
+
+    #[test]
+    fn create_reproducible_app_archive() {
+        let first = build_archive_bytes();
+        let second = build_archive_bytes();
+
+        assert!(first == second, "archives differ");
+
+        let mut archive = Archive::new(GzDecoder::new(first.as_slice()));
+        for entry in archive.entries().unwrap() {
+            let header = entry.unwrap().header().clone();
+            // Commit time of the test app's HEAD
+            assert_eq!(1552516269, header.mtime().unwrap());
+            assert_eq!(0, header.uid().unwrap());
+            assert_eq!(0, header.gid().unwrap());
+            assert_eq!(0o644, header.mode().unwrap());
+        }
+    }
+
+    #[test]
+    fn clones_app_at_git_ref() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app = App::new(get_test_app_path(dir.path()))
+            .with_source(Source::GitRef("v0.3.0".to_owned()));
+
+        let clone = app.clone().unwrap();
+
+        assert_eq!("0.3.0", clone.app_info.version().to_string());
+        let mut package_json_path = clone.tmp_dir.path().to_path_buf();
+        package_json_path.push(APP_ID);
+        package_json_path.push("package.json");
+        assert!(package_json_path.exists(), "v0.3.0 is not checked out");
+    }
+
+    #[test]
+    fn clones_app_at_remote_branch() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app = App::new(get_test_app_path(dir.path()))
+            .with_source(Source::GitRef("stable16".to_owned()));
+
+        app.clone().unwrap();
+    }
+
+    #[test]
+    fn fails_cloning_app_at_unknown_git_ref() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app = App::new(get_test_app_path(dir.path()))
+            .with_source(Source::GitRef("v9.9.9".to_owned()));
+
+        assert!(app.clone().is_err());
+    }
+
+    #[test]
+    fn keeps_build_dir_when_checkout_fails() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            "[package]\nkeep_build_dir_on_failure = true\n",
+        )
+        .unwrap();
+        let app = App::new(app_path.clone()).with_source(Source::GitRef("v9.9.9".to_owned()));
+
+        assert!(app.clone().is_err());
+
+        assert_eq!(
+            1,
+            crate::packaging::build_dir::remove_kept(&app_path).unwrap()
+        );
+    }
+
+    fn commit_all(repo: &git2::Repository, message: &str) {
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn clones_app_with_submodules() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let lib_dir = TempDir::new("krankerl-test-lib").unwrap();
+        let lib = git2::Repository::init(lib_dir.path()).unwrap();
+        std::fs::write(lib_dir.path().join("lib.php"), "<?php").unwrap();
+        commit_all(&lib, "Add lib");
+        let app_repo = git2::Repository::open(get_test_app_path(dir.path())).unwrap();
+        let mut submodule = app_repo
+            .submodule(
+                lib_dir.path().to_str().unwrap(),
+                Path::new("vendor/lib"),
+                true,
+            )
+            .unwrap();
+        submodule.clone(None).unwrap();
+        submodule.add_finalize().unwrap();
+        commit_all(&app_repo, "Add submodule");
+        let app = App::new(get_test_app_path(dir.path()));
+
+        let clone = app.clone().unwrap();
+
+        let mut lib_path = clone.tmp_dir.path().to_path_buf();
+        lib_path.push(APP_ID);
+        lib_path.push("vendor");
+        lib_path.push("lib");
+        lib_path.push("lib.php");
+        assert!(lib_path.exists(), "submodule is not checked out");
+        let built = clone.install_dependencies().unwrap().build().unwrap();
+        let file_list = build_file_list(
+            &tmp_app_path(built.tmp_dir.path(), APP_ID),
+            &PackageConfig::default(),
+        )
+        .unwrap();
+        assert!(file_list
+            .iter()
+            .any(|e| e.path().ends_with("vendor/lib/lib.php")));
+        assert!(!file_list.iter().any(|e| e.file_name() == ".git"));
+    }
+
+    #[test]
+    fn copies_working_tree() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(app_path.join("uncommitted.txt"), "dirty").unwrap();
+        std::fs::write(app_path.join("ignored.txt"), "ignored").unwrap();
+        std::fs::write(app_path.join(".gitignore"), "ignored.txt").unwrap();
+        let app = App::new(app_path).with_source(Source::WorkingTree { gitignore: true });
+
+        let clone = app.clone().unwrap();
+
+        let cloned_app_dir = tmp_app_path(clone.tmp_dir.path(), APP_ID);
+        assert!(cloned_app_dir.join("uncommitted.txt").exists());
+        assert!(!cloned_app_dir.join("ignored.txt").exists());
+        assert!(!cloned_app_dir.join(".git").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_symlinks_of_working_tree() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::create_dir(dir.path().join("shared")).unwrap();
+        std::os::unix::fs::symlink("../shared", app_path.join("vendor")).unwrap();
+        let app = App::new(app_path).with_source(Source::WorkingTree { gitignore: false });
+
+        let clone = app.clone().unwrap();
+
+        let cloned_app_dir = tmp_app_path(clone.tmp_dir.path(), APP_ID);
+        assert_eq!(
+            Path::new("../shared"),
+            std::fs::read_link(cloned_app_dir.join("vendor")).unwrap()
+        );
+    }
+
+    #[test]
+    fn create_working_tree_app_archive() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        let app = App::new(app_path.clone()).with_source(Source::WorkingTree { gitignore: false });
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let mut artifacts_path = app_path;
+        artifacts_path.push("build");
+        artifacts_path.push("artifacts");
+        assert!(artifacts_path
+            .join(format!("{}-working-tree.tar.gz", APP_ID))
+            .exists());
+        assert!(!artifacts_path.join(format!("{}.tar.gz", APP_ID)).exists());
+    }
+
+    #[test]
+    fn keeps_previous_app_archive_on_failure() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        let artifacts_path = app_path.join("build").join("artifacts");
+        std::fs::create_dir_all(&artifacts_path).unwrap();
+        std::fs::write(
+            artifacts_path.join(format!("{}.tar.gz", APP_ID)),
+            "previous",
+        )
+        .unwrap();
+        // Files that can't be opened make archiving fail
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            "[package]\nbefore_cmds = [\"ln -s missing broken\"]",
+        )
+        .unwrap();
+        let app = App::new(app_path.clone());
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert!(built.into_archive().is_err());
+
+        assert_eq!(
+            "previous",
+            std::fs::read_to_string(artifacts_path.join(format!("{}.tar.gz", APP_ID))).unwrap()
+        );
+        let staged = std::fs::read_dir(app_path.join("build"))
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with(".artifacts")
+            })
+            .count();
+        assert_eq!(0, staged, "staged artifacts were not cleaned up");
+    }
+
+    #[test]
+    fn create_app_archive_with_profile() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            r#"
+            [package]
+            before_cmds = ["touch default.txt"]
+
+            [package.profiles.nightly]
+            before_cmds = ["touch nightly.txt"]
+            artifact_name = "{app_id}-{profile}"
+            "#,
+        )
+        .unwrap();
+        let app = App::new(app_path.clone()).with_profile(Some("nightly".to_owned()));
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let archive_path = app_path
+            .join("build")
+            .join("artifacts")
+            .join(format!("{}-nightly.tar.gz", APP_ID));
+        let mut archive = Archive::new(GzDecoder::new(std::fs::File::open(archive_path).unwrap()));
+        let paths: Vec<PathBuf> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_path_buf())
+            .collect();
+        assert!(paths.contains(&Path::new(APP_ID).join("nightly.txt")));
+        assert!(!paths.contains(&Path::new(APP_ID).join("default.txt")));
+    }
+
+    #[test]
+    fn writes_build_info_into_app() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        let head = artifacts::head_commit(&app_path).unwrap().unwrap();
+        let built = App::new(app_path.clone())
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let archive_path = app_path
+            .join("build")
+            .join("artifacts")
+            .join(format!("{}.tar.gz", APP_ID));
+        let mut archive = Archive::new(GzDecoder::new(std::fs::File::open(archive_path).unwrap()));
+        let mut entry = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap())
+            .find(|e| {
+                e.path().unwrap() == Path::new(APP_ID).join("appinfo").join("build-info.json")
+            })
+            .expect("build info is missing");
+        let build_info: serde_json::Value = serde_json::from_reader(&mut entry).unwrap();
+        assert_eq!(head.to_string(), build_info["git_commit"]);
+        assert_eq!(false, build_info["dirty"]);
+        assert_eq!(serde_json::Value::Null, build_info["profile"]);
+    }
+
+    #[test]
+    fn signs_app_for_code_integrity_check() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("assets")
+            .join("code-signing");
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            format!(
+                r#"
+                [package.code_signing]
+                enabled = true
+                key = {:?}
+                certificate = {:?}
+                "#,
+                assets.join("recommendations.key"),
+                assets.join("recommendations.crt"),
+            ),
+        )
+        .unwrap();
+        let built = App::new(app_path.clone())
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let archive_path = app_path
+            .join("build")
+            .join("artifacts")
+            .join(format!("{}.tar.gz", APP_ID));
+        let mut archive = Archive::new(GzDecoder::new(std::fs::File::open(archive_path).unwrap()));
+        let mut entry = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap())
+            .find(|e| e.path().unwrap() == Path::new(APP_ID).join("appinfo").join("signature.json"))
+            .expect("signature is missing");
+        let signature: serde_json::Value = serde_json::from_reader(&mut entry).unwrap();
+        let hashes = signature["hashes"].as_object().unwrap();
+        assert!(hashes.contains_key("appinfo/info.xml"));
+        assert!(hashes.contains_key("appinfo/build-info.json"));
+        assert!(!hashes.contains_key("appinfo/signature.json"));
+        assert!(signature["certificate"]
+            .as_str()
+            .unwrap()
+            .starts_with("-----BEGIN CERTIFICATE-----"));
+    }
+
+    #[test]
+    fn keeps_previous_app_archives() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            r#"
+            [package]
+            artifact_name = "{app_id}-{version}-{profile}"
+            keep_artifacts = 2
+
+            [package.profiles.a]
+            [package.profiles.b]
+            [package.profiles.c]
+            "#,
+        )
+        .unwrap();
+        let version = get_appinfo(&app_path).unwrap().version().to_string();
+
+        for profile in &["a", "b", "c"] {
+            App::new(app_path.clone())
+                .with_profile(Some(profile.to_string()))
+                .clone()
+                .unwrap()
+                .install_dependencies()
+                .unwrap()
+                .build()
+                .unwrap()
+                .into_archive()
+                .unwrap();
+        }
+
+        let artifacts_path = app_path.join("build").join("artifacts");
+        let archive_name = |profile| format!("{}-{}-{}.tar.gz", APP_ID, version, profile);
+        assert!(!artifacts_path.join(archive_name("a")).exists());
+        assert!(artifacts_path.join(archive_name("b")).exists());
+        assert!(artifacts_path.join(archive_name("c")).exists());
+        assert!(artifacts_path
+            .join(format!("{}.sha256", archive_name("b")))
+            .exists());
+    }
+
+    #[test]
+    fn create_app_archive_with_file_rules() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            r#"
+            [package]
+            before_cmds = ["mkdir -p js && touch js/app.js js/app.js.map README.md"]
+            include = ["/appinfo/", "/js/"]
+            exclude = ["*.map"]
+            "#,
+        )
+        .unwrap();
+        let app = App::new(app_path.clone());
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let archive_path = app_path
+            .join("build")
+            .join("artifacts")
+            .join(format!("{}.tar.gz", APP_ID));
+        let mut archive = Archive::new(GzDecoder::new(std::fs::File::open(archive_path).unwrap()));
+        let paths: Vec<PathBuf> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_path_buf())
+            .collect();
+        let base = Path::new(APP_ID);
+        assert!(paths.contains(&base.join("appinfo").join("info.xml")));
+        assert!(paths.contains(&base.join("js").join("app.js")));
+        assert!(!paths.contains(&base.join("js").join("app.js.map")));
+        assert!(!paths.contains(&base.join("README.md")));
+    }
+
+    #[test]
+    fn lists_packaged_files() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            r#"
+            [package]
+            before_cmds = ["echo built > built.txt"]
+            exclude = ["/README.md"]
+            "#,
+        )
+        .unwrap();
+        std::fs::write(app_path.join("README.md"), "readme").unwrap();
+        commit_all(&git2::Repository::open(&app_path).unwrap(), "Add readme");
+        let app = App::new(app_path.clone());
+
+        let file_list = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap()
+            .into_file_list()
+            .unwrap();
+
+        assert!(file_list
+            .included()
+            .contains(&(PathBuf::from("built.txt"), 6)));
+        assert!(file_list
+            .included()
+            .iter()
+            .any(|(path, _)| path == Path::new("appinfo/info.xml")));
+        assert!(!file_list
+            .included()
+            .iter()
+            .any(|(path, _)| path == Path::new("README.md")));
+        assert_eq!(1, file_list.excluded());
+        assert!(!app_path.join("build").join("artifacts").exists());
+    }
+
+    #[test]
+    fn lists_files_without_building() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            "[package]\nbefore_cmds = [\"touch built.txt\"]",
+        )
+        .unwrap();
+        let app = App::new(app_path.clone());
+
+        let file_list = app.clone().unwrap().skip_build().into_file_list().unwrap();
+
+        assert!(!file_list
+            .included()
+            .iter()
+            .any(|(path, _)| path == Path::new("built.txt")));
+    }
+
+    #[test]
+    fn writes_manifest_and_checksums() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        let app = App::new(app_path.clone());
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let artifacts_path = app_path.join("build").join("artifacts");
+        let manifest: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(artifacts_path.join(format!("{}.manifest.json", APP_ID)))
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(APP_ID, manifest["app_id"]);
+        assert_eq!(format!("{}.tar.gz", APP_ID), manifest["archive"]);
+        let info_xml = std::fs::read(app_path.join("appinfo").join("info.xml")).unwrap();
+        let entry = manifest["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["path"] == format!("{}/appinfo/info.xml", APP_ID))
+            .unwrap();
+        assert_eq!(info_xml.len() as u64, entry["size"]);
+        assert_eq!(
+            hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&info_xml)),
+            entry["sha256"]
+        );
+
+        let archive = std::fs::read(artifacts_path.join(format!("{}.tar.gz", APP_ID))).unwrap();
+        assert_eq!(
+            format!(
+                "{}  {}.tar.gz\n",
+                hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&archive)),
+                APP_ID
+            ),
+            std::fs::read_to_string(artifacts_path.join(format!("{}.tar.gz.sha256", APP_ID)))
+                .unwrap()
+        );
+        assert!(artifacts_path
+            .join(format!("{}.tar.gz.sha512", APP_ID))
+            .exists());
+    }
+
+    #[test]
+    fn writes_sbom_next_to_archive() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        let built = App::new(app_path.clone())
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let sbom_path = app_path
+            .join("build")
+            .join("artifacts")
+            .join(format!("{}.cdx.json", APP_ID));
+        let bom: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(sbom_path).unwrap()).unwrap();
+        assert_eq!("CycloneDX", bom["bomFormat"]);
+        assert_eq!(APP_ID, bom["metadata"]["component"]["name"]);
+    }
+
+    #[test]
+    fn writes_sbom_without_invalid_lock_files() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("composer.lock"),
+            r#"{"packages": [{"name": "a/b", "version": "1.0.0", "license": "MIT"}]}"#,
+        )
+        .unwrap();
+        commit_all(&git2::Repository::open(&app_path).unwrap(), "Add lock file");
+        let build = || {
+            App::new(app_path.clone())
+                .clone()
+                .unwrap()
+                .install_dependencies()
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+
+        build().into_archive().unwrap();
+
+        let sbom_path = app_path
+            .join("build")
+            .join("artifacts")
+            .join(format!("{}.cdx.json", APP_ID));
+        let bom: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(sbom_path).unwrap()).unwrap();
+        assert_eq!(0, bom["components"].as_array().unwrap().len());
+
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            "[package.licenses]\nallowed = [\"MIT\"]\ncheck = true",
+        )
+        .unwrap();
+        commit_all(
+            &git2::Repository::open(&app_path).unwrap(),
+            "Check licenses",
+        );
+        assert!(build().into_archive().is_err());
+    }
+
+    #[test]
+    fn verifies_app_archive() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(app_path.join("krankerl.toml"), "[package]\nverify = true").unwrap();
+        let app = App::new(app_path.clone());
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+    }
+
+    #[test]
+    fn fails_when_licenses_are_not_allowed() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            r#"
+            [package]
+            before_cmds = ['''echo '{"packages": [{"name": "a/b", "version": "1.0.0", "license": ["BSD-4-Clause"]}]}' > composer.lock''']
+
+            [package.licenses]
+            allowed = ["MIT"]
+            check = true
+            "#,
+        )
+        .unwrap();
+        let built = App::new(app_path.clone())
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let error = match built.into_archive() {
+            Ok(_) => panic!("licenses were not checked"),
+            Err(e) => e,
+        };
+
+        assert!(error.to_string().contains("a/b 1.0.0"), "{}", error);
+        assert!(!app_path
+            .join("build")
+            .join("artifacts")
+            .join(format!("{}.tar.gz", APP_ID))
+            .exists());
+    }
+
+    #[test]
+    fn fails_when_size_limits_are_exceeded() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        let artifacts_path = app_path.join("build").join("artifacts");
+        std::fs::create_dir_all(&artifacts_path).unwrap();
+        std::fs::write(
+            artifacts_path.join(format!("{}.tar.gz", APP_ID)),
+            "previous",
+        )
+        .unwrap();
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            r#"
+            [package]
+            before_cmds = ["head -c 4096 /dev/zero > large.bin"]
+
+            [package.limits]
+            max_file_size = "1 KiB"
+            "#,
+        )
+        .unwrap();
+        let app = App::new(app_path.clone());
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let error = match built.into_archive() {
+            Ok(_) => panic!("size limits were not enforced"),
+            Err(e) => e,
+        };
+
+        assert!(error.to_string().contains("large.bin"), "{}", error);
+        assert_eq!(
+            "previous",
+            std::fs::read_to_string(artifacts_path.join(format!("{}.tar.gz", APP_ID))).unwrap()
+        );
+    }
+
+    #[test]
+    fn passes_size_limits() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            "[package.limits]\nmax_archive_size = \"1 MiB\"\nmax_file_size = \"1 MiB\"",
+        )
+        .unwrap();
+        let app = App::new(app_path.clone());
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+    }
+
+    #[test]
+    fn runs_after_cmds() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            r#"
+            [package]
+            after_cmds = ["echo $KRANKERL_APP_ID > $KRANKERL_ARTIFACT.txt"]
+            "#,
+        )
+        .unwrap();
+        let app = App::new(app_path.clone());
+        let built = app
+            .clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        built.into_archive().unwrap();
+
+        let output_path = app_path
+            .join("build")
+            .join("artifacts")
+            .join(format!("{}.tar.gz.txt", APP_ID));
+        assert_eq!(
+            format!("{}\n", APP_ID),
+            std::fs::read_to_string(output_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn replaces_placeholders_in_commands() {
+        let dir = create_test_app_dir(MINIMALIST_APP);
+        let app_path = get_test_app_path(dir.path());
+        std::fs::write(
+            app_path.join("krankerl.toml"),
+            r#"
+            [package]
+            before_cmds = ["echo {app_id} {version} {git_commit} > {source_dir}/build/vars.txt"]
+            "#,
+        )
+        .unwrap();
+        let app = App::new(app_path.clone());
+
+        app.clone()
+            .unwrap()
+            .install_dependencies()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let app_info = get_appinfo(&app_path).unwrap();
+        let commit = artifacts::head_commit(&app_path).unwrap().unwrap();
+        assert_eq!(
+            format!("{} {} {}\n", APP_ID, app_info.version(), commit),
+            std::fs::read_to_string(app_path.join("build").join("vars.txt")).unwrap()
+        );
+    }
 }
@@ -0,0 +1,216 @@
+use std::fs::{self, File};
+use std::path::Path;
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Report, Result};
+use nextcloud_appinfo::AppInfo;
+use xpath_reader::{Context, Reader};
+
+use crate::packaging::lock_files::Package;
+
+/// CycloneDX document, see https://cyclonedx.org/docs/1.5/json/
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Bom {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: Metadata,
+    components: Vec<Component>,
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Serialize)]
+struct Metadata {
+    tools: Tools,
+    component: Component,
+}
+
+#[derive(Debug, Serialize)]
+struct Tools {
+    components: Vec<Component>,
+}
+
+#[derive(Debug, Serialize)]
+struct Component {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref", skip_serializing_if = "Option::is_none")]
+    bom_ref: Option<String>,
+    name: String,
+    version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    purl: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    licenses: Vec<License>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum License {
+    Id { license: LicenseId },
+    Expression { expression: String },
+}
+
+#[derive(Debug, Serialize)]
+struct LicenseId {
+    id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Dependency {
+    #[serde(rename = "ref")]
+    reference: String,
+    depends_on: Vec<String>,
+}
+
+/// The SBOM of `news.tar.gz` is written to `news.cdx.json`
+pub fn sbom_name(archive_name: &str) -> String {
+    format!("{}.cdx.json", archive_name.trim_end_matches(".tar.gz"))
+}
+
+fn license(license: &str) -> License {
+    if license.contains(' ') || license.contains('(') {
+        License::Expression {
+            expression: license.to_owned(),
+        }
+    } else {
+        License::Id {
+            license: LicenseId {
+                id: license.to_owned(),
+            },
+        }
+    }
+}
+
+/// Translates the short licence names of `info.xml` to SPDX identifiers
+fn spdx_licence(licence: &str) -> String {
+    match licence.to_lowercase().as_str() {
+        "agpl" => "AGPL-3.0-or-later".to_owned(),
+        "apache" => "Apache-2.0".to_owned(),
+        "gpl3" => "GPL-3.0-or-later".to_owned(),
+        "mit" => "MIT".to_owned(),
+        "mpl" => "MPL-2.0".to_owned(),
+        _ => licence.to_owned(),
+    }
+}
+
+fn app_licences(app_path: &Path) -> Result<Vec<String>> {
+    let xml = fs::read_to_string(app_path.join("appinfo").join("info.xml"))?;
+    let context = Context::new();
+    let reader = Reader::from_str(&xml, Some(&context))
+        .map_err(|e| Report::msg(format!("Failed to parse info.xml: {}", e)))?;
+    let licences: Vec<String> = reader
+        .read("//info/licence")
+        .map_err(|e| Report::msg(format!("Failed to read licence from info.xml: {}", e)))?;
+    Ok(licences
+        .iter()
+        .map(|licence| spdx_licence(licence))
+        .collect())
+}
+
+fn package_component(package: &Package) -> Component {
+    Component {
+        kind: "library",
+        bom_ref: Some(package.purl()),
+        name: package.name.clone(),
+        version: package.version.clone(),
+        purl: Some(package.purl()),
+        licenses: package.licenses.iter().map(|l| license(l)).collect(),
+    }
+}
+
+/// Writes a CycloneDX SBOM with the app at `app_path` as root component
+/// that depends on all given packages
+pub fn write(path: &Path, app_path: &Path, app_info: &AppInfo, packages: &[Package]) -> Result<()> {
+    let root = Component {
+        kind: "application",
+        bom_ref: Some(app_info.id().clone()),
+        name: app_info.id().clone(),
+        version: app_info.version().to_string(),
+        purl: None,
+        licenses: app_licences(app_path)?.iter().map(|l| license(l)).collect(),
+    };
+    let bom = Bom {
+        bom_format: "CycloneDX",
+        spec_version: "1.5",
+        version: 1,
+        dependencies: vec![Dependency {
+            reference: app_info.id().clone(),
+            depends_on: packages.iter().map(Package::purl).collect(),
+        }],
+        metadata: Metadata {
+            tools: Tools {
+                components: vec![Component {
+                    kind: "application",
+                    bom_ref: None,
+                    name: "krankerl".to_owned(),
+                    version: env!("CARGO_PKG_VERSION").to_owned(),
+                    purl: None,
+                    licenses: vec![],
+                }],
+            },
+            component: root,
+        },
+        components: packages.iter().map(package_component).collect(),
+    };
+
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(file, &bom)
+        .wrap_err_with(|| format!("Failed to write SBOM {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use nextcloud_appinfo::get_appinfo;
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::packaging::lock_files::Ecosystem;
+
+    #[test]
+    fn names_sbom_after_archive() {
+        assert_eq!("news-1.0.cdx.json", sbom_name("news-1.0.tar.gz"));
+    }
+
+    #[test]
+    fn writes_cyclonedx_sbom() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        fs::create_dir(tmp.path().join("appinfo")).unwrap();
+        fs::write(
+            tmp.path().join("appinfo").join("info.xml"),
+            "<?xml version=\"1.0\"?><info><id>news</id><name>News</name>\
+             <version>1.0.0</version><licence>agpl</licence></info>",
+        )
+        .unwrap();
+        let app_info = get_appinfo(tmp.path()).unwrap();
+        let packages = vec![Package {
+            ecosystem: Ecosystem::Npm,
+            name: "dompurify".to_owned(),
+            version: "3.0.0".to_owned(),
+            licenses: vec!["(MPL-2.0 OR Apache-2.0)".to_owned()],
+        }];
+        let path = tmp.path().join("news.cdx.json");
+
+        write(&path, tmp.path(), &app_info, &packages).unwrap();
+
+        let bom: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!("CycloneDX", bom["bomFormat"]);
+        let root = &bom["metadata"]["component"];
+        assert_eq!("news", root["name"]);
+        assert_eq!("1.0.0", root["version"]);
+        assert_eq!("AGPL-3.0-or-later", root["licenses"][0]["license"]["id"]);
+        let component = &bom["components"][0];
+        assert_eq!("pkg:npm/dompurify@3.0.0", component["purl"]);
+        assert_eq!(
+            "(MPL-2.0 OR Apache-2.0)",
+            component["licenses"][0]["expression"]
+        );
+        assert_eq!(
+            "pkg:npm/dompurify@3.0.0",
+            bom["dependencies"][0]["dependsOn"][0]
+        );
+    }
+}
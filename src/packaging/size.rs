@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+
+use color_eyre::{Report, Result};
+
+use crate::config::app::LimitsConfig;
+use crate::packaging::manifest::ManifestEntry;
+
+/// Number of files listed in the size report
+const LARGEST_FILES: usize = 10;
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+pub fn format_size_delta(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_size(delta.unsigned_abs()))
+}
+
+/// Path of a manifest entry without the app's directory
+fn app_relative(entry: &ManifestEntry) -> &Path {
+    let path = Path::new(&entry.path);
+    let mut components = path.components();
+    match components.next() {
+        Some(Component::Normal(_)) => components.as_path(),
+        _ => path,
+    }
+}
+
+/// Sums up the sizes of the files by their top-level directory. Files in
+/// the app's root directory are listed on their own.
+fn sizes_by_directory(files: &[ManifestEntry]) -> Vec<(String, u64)> {
+    let mut sizes: BTreeMap<String, u64> = BTreeMap::new();
+    for entry in files {
+        let path = app_relative(entry);
+        let mut components = path.components();
+        let first = components
+            .next()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let key = if components.next().is_some() {
+            format!("{}/", first)
+        } else {
+            first
+        };
+        *sizes.entry(key).or_default() += entry.size;
+    }
+
+    let mut sizes: Vec<(String, u64)> = sizes.into_iter().collect();
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sizes
+}
+
+fn largest_files(files: &[ManifestEntry], count: usize) -> Vec<&ManifestEntry> {
+    let mut largest: Vec<&ManifestEntry> = files.iter().collect();
+    largest.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    largest.truncate(count);
+    largest
+}
+
+pub fn print_report(files: &[ManifestEntry], archive_size: u64) {
+    let total: u64 = files.iter().map(|entry| entry.size).sum();
+
+    println!("Size by directory:");
+    for (directory, size) in sizes_by_directory(files) {
+        println!("{:>12}  {}", format_size(size), directory);
+    }
+    println!("Largest files:");
+    for entry in largest_files(files, LARGEST_FILES) {
+        println!(
+            "{:>12}  {}",
+            format_size(entry.size),
+            app_relative(entry).to_string_lossy()
+        );
+    }
+    println!(
+        "Archive size: {} compressed, {} uncompressed",
+        format_size(archive_size),
+        format_size(total)
+    );
+}
+
+/// Fails if the archive or any of its files exceeds the configured limits
+pub fn check_limits(
+    files: &[ManifestEntry],
+    archive_size: u64,
+    limits: &LimitsConfig,
+) -> Result<()> {
+    let mut violations = vec![];
+    if let Some(max) = limits.max_archive_size() {
+        if archive_size > max {
+            violations.push(format!(
+                "The archive is {} ({} bytes), the limit is {} ({} bytes)",
+                format_size(archive_size),
+                archive_size,
+                format_size(max),
+                max
+            ));
+        }
+    }
+    if let Some(max) = limits.max_file_size() {
+        for entry in files.iter().filter(|entry| entry.size > max) {
+            violations.push(format!(
+                "{} is {}, the limit is {}",
+                app_relative(entry).to_string_lossy(),
+                format_size(entry.size),
+                format_size(max)
+            ));
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Report::msg(format!(
+            "Size limits exceeded:\n{}",
+            violations
+                .iter()
+                .map(|violation| format!("  - {}", violation))
+                .collect::<Vec<String>>()
+                .join("\n")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, size: u64) -> ManifestEntry {
+        ManifestEntry {
+            path: path.to_owned(),
+            size,
+            sha256: String::new(),
+        }
+    }
+
+    fn files() -> Vec<ManifestEntry> {
+        vec![
+            entry("news/appinfo/info.xml", 800),
+            entry("news/js/news.js", 5000),
+            entry("news/js/news.js.map", 9000),
+            entry("news/lib/App.php", 300),
+            entry("news/README.md", 100),
+        ]
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!("0 B", format_size(0));
+        assert_eq!("1023 B", format_size(1023));
+        assert_eq!("1.5 KiB", format_size(1536));
+        assert_eq!("2.0 MiB", format_size(2 * 1024 * 1024));
+    }
+
+    #[test]
+    fn formats_size_deltas() {
+        assert_eq!("+0 B", format_size_delta(0));
+        assert_eq!("+1.5 KiB", format_size_delta(1536));
+        assert_eq!("-12 B", format_size_delta(-12));
+    }
+
+    #[test]
+    fn sums_sizes_by_directory() {
+        assert_eq!(
+            vec![
+                ("js/".to_owned(), 14000),
+                ("appinfo/".to_owned(), 800),
+                ("lib/".to_owned(), 300),
+                ("README.md".to_owned(), 100),
+            ],
+            sizes_by_directory(&files())
+        );
+    }
+
+    #[test]
+    fn finds_largest_files() {
+        let files = files();
+
+        let largest: Vec<&str> = largest_files(&files, 2)
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+
+        assert_eq!(vec!["news/js/news.js.map", "news/js/news.js"], largest);
+    }
+
+    #[test]
+    fn passes_without_limits() {
+        assert!(check_limits(&files(), 1_000_000, &LimitsConfig::default()).is_ok());
+    }
+}
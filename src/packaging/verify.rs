@@ -0,0 +1,289 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::{Report, Result};
+use flate2::read::GzDecoder;
+use nextcloud_appinfo::{get_appinfo, AppInfo};
+use tar::Archive;
+use tempdir::TempDir;
+
+/// Returns the components of an archive path, or `None` if it is absolute
+/// or contains `..`
+fn normal_components(path: &Path) -> Option<Vec<String>> {
+    let mut components = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_string_lossy().into_owned()),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(components)
+}
+
+/// Resolves the target of a symlink without touching the file system.
+/// Returns `false` if it points outside of the top-level directory.
+fn symlink_stays_inside(link: &[String], target: &Path) -> bool {
+    if target.is_absolute() {
+        return false;
+    }
+    // Start in the directory that contains the link
+    let mut resolved: Vec<&str> = link[..link.len() - 1].iter().map(String::as_str).collect();
+    for component in target.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name.to_str().unwrap_or("")),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+                if resolved.is_empty() {
+                    return false;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+fn parse_info_xml(xml: &[u8]) -> Result<AppInfo> {
+    let tmp = TempDir::new("krankerl")?;
+    fs::create_dir(tmp.path().join("appinfo"))?;
+    fs::write(tmp.path().join("appinfo").join("info.xml"), xml)?;
+    Ok(get_appinfo(tmp.path())?)
+}
+
+/// Collects everything that would keep the archive from being installed
+fn find_problems(archive_path: &Path, source_info: &AppInfo) -> Result<Vec<String>> {
+    let file = File::open(archive_path)
+        .wrap_err_with(|| format!("Failed to open archive {:?}", archive_path))?;
+    let mut archive = Archive::new(GzDecoder::new(file));
+    let mut problems = vec![];
+    let mut top_level = BTreeSet::new();
+    let mut info_xml = None;
+
+    for entry in archive
+        .entries()
+        .wrap_err("Failed to read archive entries")?
+    {
+        let mut entry = entry.wrap_err("Failed to read archive entry")?;
+        let path: PathBuf = entry.path()?.into_owned();
+        let components = match normal_components(&path) {
+            Some(components) if !components.is_empty() => components,
+            Some(_) => continue,
+            None => {
+                problems.push(format!("{:?} is absolute or contains ..", path));
+                continue;
+            }
+        };
+        top_level.insert(components[0].clone());
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry.link_name()?.map(|target| target.into_owned());
+            let inside = match target {
+                Some(target) if entry_type.is_symlink() => {
+                    symlink_stays_inside(&components, &target)
+                }
+                // Hard links point to another entry of the archive
+                Some(target) => normal_components(&target)
+                    .map(|target| target.first() == components.first())
+                    .unwrap_or(false),
+                None => false,
+            };
+            if !inside {
+                problems.push(format!("Link {:?} points outside of the app", path));
+            }
+        }
+
+        if components.len() == 3 && components[1] == "appinfo" && components[2] == "info.xml" {
+            let mut xml = vec![];
+            entry.read_to_end(&mut xml)?;
+            info_xml = Some(xml);
+        }
+    }
+
+    if top_level.len() != 1 {
+        problems.push(format!(
+            "Expected exactly one top-level directory, found {}",
+            top_level.len()
+        ));
+    }
+    let app_info = match info_xml.map(|xml| parse_info_xml(&xml)) {
+        Some(Ok(app_info)) => app_info,
+        Some(Err(e)) => {
+            problems.push(format!("appinfo/info.xml can't be parsed: {}", e));
+            return Ok(problems);
+        }
+        None => {
+            problems.push("appinfo/info.xml is missing".to_owned());
+            return Ok(problems);
+        }
+    };
+    if !top_level.contains(app_info.id()) {
+        problems.push(format!(
+            "Top-level directory does not match the app id <{}>",
+            app_info.id()
+        ));
+    }
+    if app_info.version() != source_info.version() {
+        problems.push(format!(
+            "Version {} does not match version {} of the source tree",
+            app_info.version(),
+            source_info.version()
+        ));
+    }
+
+    Ok(problems)
+}
+
+/// Checks that the archive can be installed as the given app
+pub fn verify_archive(archive_path: &Path, source_info: &AppInfo) -> Result<()> {
+    let problems = find_problems(archive_path, source_info)?;
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    Err(Report::msg(format!(
+        "Archive {:?} is invalid:\n{}",
+        archive_path,
+        problems
+            .iter()
+            .map(|problem| format!("  - {}", problem))
+            .collect::<Vec<String>>()
+            .join("\n")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tar::{Builder, EntryType, Header};
+
+    use super::*;
+
+    fn info_xml(id: &str, version: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\"?><info><id>{}</id><name>App</name><version>{}</version></info>",
+            id, version
+        )
+    }
+
+    fn source_info() -> AppInfo {
+        parse_info_xml(info_xml("news", "1.0.0").as_bytes()).unwrap()
+    }
+
+    enum TestEntry<'a> {
+        File(&'a str, &'a str),
+        Symlink(&'a str, &'a str),
+    }
+
+    fn build_archive(dir: &Path, entries: &[TestEntry]) -> PathBuf {
+        let path = dir.join("app.tar.gz");
+        let mut builder = Builder::new(GzEncoder::new(
+            File::create(&path).unwrap(),
+            Compression::default(),
+        ));
+        for entry in entries {
+            let mut header = Header::new_gnu();
+            header.set_mode(0o644);
+            match entry {
+                TestEntry::File(path, content) => {
+                    header.set_size(content.len() as u64);
+                    // Bypass the path validation of the tar crate
+                    header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+                    header.set_cksum();
+                    builder.append(&header, content.as_bytes()).unwrap();
+                }
+                TestEntry::Symlink(path, target) => {
+                    header.set_entry_type(EntryType::Symlink);
+                    header.set_size(0);
+                    builder.append_link(&mut header, path, target).unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn accepts_valid_archive() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let xml = info_xml("news", "1.0.0");
+        let archive = build_archive(
+            tmp.path(),
+            &[
+                TestEntry::File("news/appinfo/info.xml", &xml),
+                TestEntry::File("news/lib/App.php", "<?php"),
+                TestEntry::Symlink("news/lib/Link.php", "App.php"),
+            ],
+        );
+
+        assert!(verify_archive(&archive, &source_info()).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_info_xml() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let archive = build_archive(tmp.path(), &[TestEntry::File("news/lib/App.php", "")]);
+
+        let problems = find_problems(&archive, &source_info()).unwrap();
+
+        assert_eq!(vec!["appinfo/info.xml is missing"], problems);
+    }
+
+    #[test]
+    fn rejects_wrong_top_level_directory() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let xml = info_xml("news", "1.0.0");
+        let archive = build_archive(
+            tmp.path(),
+            &[
+                TestEntry::File("mail/appinfo/info.xml", &xml),
+                TestEntry::File("other.txt", ""),
+            ],
+        );
+
+        let problems = find_problems(&archive, &source_info()).unwrap();
+
+        assert_eq!(2, problems.len());
+    }
+
+    #[test]
+    fn rejects_unsafe_paths() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let xml = info_xml("news", "1.0.0");
+        let archive = build_archive(
+            tmp.path(),
+            &[
+                TestEntry::File("news/appinfo/info.xml", &xml),
+                TestEntry::File("/etc/passwd", ""),
+                TestEntry::File("news/../../escape", ""),
+                TestEntry::Symlink("news/lib/up", "../../.."),
+                TestEntry::Symlink("news/lib/abs", "/etc/passwd"),
+            ],
+        );
+
+        let problems = find_problems(&archive, &source_info()).unwrap();
+
+        assert_eq!(4, problems.len(), "{:?}", problems);
+    }
+
+    #[test]
+    fn rejects_version_mismatch() {
+        let tmp = TempDir::new("krankerl-test").unwrap();
+        let xml = info_xml("news", "0.9.0");
+        let archive = build_archive(
+            tmp.path(),
+            &[TestEntry::File("news/appinfo/info.xml", &xml)],
+        );
+
+        let problems = find_problems(&archive, &source_info()).unwrap();
+
+        assert_eq!(1, problems.len());
+        assert!(problems[0].contains("0.9.0"));
+    }
+}
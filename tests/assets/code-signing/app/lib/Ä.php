@@ -0,0 +1,6 @@
+<?php
+
+namespace OCA\Recommendations;
+
+class Ä {
+}